@@ -0,0 +1,536 @@
+use sfdisk_sort_rs::{Alignment, Arrangement, Numbering, Pin, SortKey, Sysfs};
+
+use anyhow::{Error, Result};
+
+pub const USAGE: &str = "Usage: sfdisk-sort-rs [OPTIONS] [COMMAND]
+
+Reads `sfdisk -d` (or `sfdisk -J`) output and works with its partition table.
+
+Commands:
+  sort                 Print the partition table sorted by start block (default)
+  validate             Check the partition layout for problems sfdisk would reject
+  info                 Print the disk header and its partitions
+  free                 Print the unpartitioned gaps, like `sfdisk -F`
+  align                Print the partitions whose start or size is not aligned
+  diff                 Print a unified diff of the original and the sorted table
+  fstab FSTAB          Print FSTAB with its /dev paths renamed after sorting
+  write                Write the sorted GPT back into the --image file
+
+Options:
+  -i, --input FILE     Read the sfdisk output from FILE instead of stdin
+      --image FILE     Read the GPT of the disk image or block device FILE instead of
+                       sfdisk output
+  -o, --output FILE    Write to FILE instead of stdout
+      --check          (sort) Only check the order, see Exit status
+      --dry-run        (write) Only print the bytes that would be written
+      --sort-by KEY    (sort, diff, fstab, write) Order partitions by `start` block
+                       (default), current `designation`, GPT `name`, or `type` (ESP, root,
+                       swap, rest)
+      --pin PART=N     (sort, diff, fstab, write) Keep PART at partition number N, where
+                       PART is a current name, `uuid:UUID` or `label:NAME`. Can be repeated
+      --start-at N     (sort, diff, fstab, write) Number partitions from N instead of 1
+      --gap N          (sort, diff, fstab, write) Leave partition number N empty. Can be
+                       repeated
+      --keep-numbers   (sort, diff, fstab, write) Reuse the partition numbers already in
+                       use, reassigned in sorted order
+      --mapping [FMT]  (sort) Print the old-to-new mapping as `table` (default) or `json`
+      --align SIZE     (validate, align) Alignment boundary in bytes, e.g. 4096 or 1M
+                       (default), or `grain` for the dump's grain. With validate,
+                       misaligned partitions are reported as problems
+      --diff           (fstab) Print a unified diff instead of the new fstab
+      --color          (diff, fstab --diff) Color the diff with ANSI escapes
+      --sysfs          Warn about partitions that differ from the running kernel's view
+                       in /sys/class/block, i.e. when the dump is stale
+      --sysfs-root DIR Like --sysfs, reading DIR instead of /sys/class/block
+      --dev-root DIR   (--sysfs, --sysfs-root) Resolve device links in DIR instead of
+                       /dev, e.g. in a copy taken with the sysfs tree
+  -h, --help           Print this help
+  -V, --version        Print the version
+
+Exit status:
+  0  success, or with --check: the partitions are already sorted
+  1  with --check: the partitions are out of order
+  2  the arguments or the input could not be read or parsed
+  3  any other error, e.g. a partition layout sfdisk would reject";
+
+/// How to print the old-to-new partition mapping, selected with `--mapping [table|json]`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MappingFormat {
+    Table,
+    Json,
+}
+
+/// What to do with the parsed disk, selected by the subcommand.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Print the sorted table, or only the old-to-new mapping
+    Sort {
+        mapping: Option<MappingFormat>,
+    },
+    /// Print the problems in the partition layout, if any, including misaligned
+    /// partitions if `align` is set
+    Validate {
+        align: Option<Alignment>,
+    },
+    /// Exit with a status telling whether the table is already sorted, printing nothing
+    Check,
+    /// Print a summary of the disk and its partitions
+    Info,
+    /// Print the unallocated gaps between the partitions
+    Free,
+    /// Print the partitions that are not aligned
+    Align {
+        align: Alignment,
+    },
+    /// Print a unified diff of the original and sorted table
+    Diff {
+        color: bool,
+    },
+    /// Rewrite the device paths in an fstab file through the old-to-new mapping
+    Fstab {
+        path: String,
+        diff: bool,
+        color: bool,
+    },
+    /// Write the sorted partition entry array into the GPT of the disk image at `path`
+    Write {
+        path: String,
+        dry_run: bool,
+    },
+    Help,
+    Version,
+}
+
+/// Parsed command-line arguments.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Args {
+    pub input: Option<String>,
+    /// Disk image to read the GPT of, instead of sfdisk output
+    pub image: Option<String>,
+    pub output: Option<String>,
+    pub command: Command,
+    /// Order and pinned numbers of the sorted partitions, for every command that sorts
+    pub arrangement: Arrangement,
+    /// sysfs block directory to cross-check the disks with, if `--sysfs` is given
+    pub sysfs: Option<Sysfs>,
+}
+
+/// Parses the command-line arguments (without the program name).
+pub fn parse(args: Vec<String>) -> Result<Args> {
+    let mut input: Option<String> = None;
+    let mut image: Option<String> = None;
+    let mut output: Option<String> = None;
+    let mut mapping: Option<MappingFormat> = None;
+    let mut sort_by: Option<SortKey> = None;
+    let mut pins: Vec<Pin> = Vec::new();
+    let mut start_at: Option<usize> = None;
+    let mut gaps: Vec<usize> = Vec::new();
+    let mut keep_numbers = false;
+    let mut align: Option<Alignment> = None;
+    let mut diff = false;
+    let mut check = false;
+    let mut dry_run = false;
+    let mut color = false;
+    let mut sysfs: Option<Sysfs> = None;
+    let mut dev_root: Option<String> = None;
+    let mut positionals: Vec<String> = Vec::new();
+
+    let mut args = args.into_iter().peekable();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                return Ok(Args {
+                    input,
+                    image,
+                    output,
+                    command: Command::Help,
+                    arrangement: Arrangement::default(),
+                    sysfs,
+                })
+            }
+            "-V" | "--version" => {
+                return Ok(Args {
+                    input,
+                    image,
+                    output,
+                    command: Command::Version,
+                    arrangement: Arrangement::default(),
+                    sysfs,
+                });
+            }
+            "-i" | "--input" => input = Some(flag_value(&arg, args.next())?),
+            "--image" => image = Some(flag_value(&arg, args.next())?),
+            "-o" | "--output" => output = Some(flag_value(&arg, args.next())?),
+            "--mapping" => {
+                mapping = Some(match args.peek().map(String::as_str) {
+                    Some("json") => {
+                        args.next();
+                        MappingFormat::Json
+                    }
+                    Some("table") => {
+                        args.next();
+                        MappingFormat::Table
+                    }
+                    _ => MappingFormat::Table,
+                });
+            }
+            "--sort-by" => {
+                let key = flag_value(&arg, args.next())?;
+                sort_by = Some(key.parse().map_err(Error::msg)?);
+            }
+            "--pin" => {
+                let pin = flag_value(&arg, args.next())?;
+                pins.push(pin.parse().map_err(Error::msg)?);
+            }
+            "--start-at" => start_at = Some(designation_value(&arg, args.next())?),
+            "--gap" => gaps.push(designation_value(&arg, args.next())?),
+            "--keep-numbers" => keep_numbers = true,
+            "--align" => {
+                let value = flag_value(&arg, args.next())?;
+                align = Some(value.parse().map_err(Error::msg)?);
+            }
+            "--diff" => diff = true,
+            "--check" => check = true,
+            "--dry-run" => dry_run = true,
+            "--color" => color = true,
+            "--sysfs" => sysfs = Some(Sysfs::default()),
+            "--sysfs-root" => sysfs = Some(Sysfs::new(flag_value(&arg, args.next())?)),
+            "--dev-root" => dev_root = Some(flag_value(&arg, args.next())?),
+            _ if arg.starts_with('-') && arg != "-" => {
+                return Err(Error::msg(format!("unknown option {}", arg)));
+            }
+            _ => positionals.push(arg),
+        }
+    }
+
+    let positionals: Vec<&str> = positionals.iter().map(String::as_str).collect();
+    let command = match positionals.as_slice() {
+        [] | ["sort"] if check => Command::Check,
+        [] | ["sort"] => Command::Sort { mapping },
+        ["validate"] => Command::Validate { align },
+        ["info"] => Command::Info,
+        ["free"] => Command::Free,
+        ["align"] => Command::Align {
+            align: align.unwrap_or_default(),
+        },
+        ["diff"] => Command::Diff { color },
+        ["fstab", path] => Command::Fstab {
+            path: String::from(*path),
+            diff,
+            color,
+        },
+        ["fstab"] => return Err(Error::msg("missing FSTAB path for command fstab")),
+        ["write"] => match &image {
+            Some(path) => Command::Write {
+                path: path.clone(),
+                dry_run,
+            },
+            None => return Err(Error::msg("command write needs --image")),
+        },
+        _ => {
+            return Err(Error::msg(format!(
+                "unexpected arguments: {}",
+                positionals.join(" ")
+            )));
+        }
+    };
+
+    // Reject flags that do nothing for the chosen command
+    let sorts = matches!(
+        command,
+        Command::Sort { .. }
+            | Command::Check
+            | Command::Diff { .. }
+            | Command::Fstab { .. }
+            | Command::Write { .. }
+    );
+    if input.is_some() && image.is_some() {
+        return Err(Error::msg("--input cannot be used with --image"));
+    }
+    if check && command != Command::Check {
+        return Err(Error::msg("--check can only be used with command sort"));
+    }
+    if dry_run && !matches!(command, Command::Write { .. }) {
+        return Err(Error::msg("--dry-run can only be used with command write"));
+    }
+    if mapping.is_some() && !matches!(command, Command::Sort { .. }) {
+        return Err(Error::msg("--mapping can only be used with command sort"));
+    }
+    if sort_by.is_some() && !sorts {
+        return Err(Error::msg(
+            "--sort-by can only be used with command sort, diff, fstab or write",
+        ));
+    }
+    if !pins.is_empty() && !sorts {
+        return Err(Error::msg(
+            "--pin can only be used with command sort, diff, fstab or write",
+        ));
+    }
+    let numbering = if keep_numbers {
+        if start_at.is_some() || !gaps.is_empty() {
+            return Err(Error::msg(
+                "--keep-numbers cannot be used with --start-at or --gap",
+            ));
+        }
+        Numbering::KeepUsed
+    } else {
+        Numbering::Sequential {
+            first: start_at.unwrap_or(1),
+            gaps,
+        }
+    };
+    if numbering != Numbering::default() && !sorts {
+        return Err(Error::msg(
+            "--start-at, --gap and --keep-numbers can only be used with command sort, diff, fstab or write",
+        ));
+    }
+    if align.is_some()
+        && !matches!(command, Command::Validate { .. } | Command::Align { .. })
+    {
+        return Err(Error::msg(
+            "--align can only be used with command validate or align",
+        ));
+    }
+    if diff && !matches!(command, Command::Fstab { .. }) {
+        return Err(Error::msg("--diff can only be used with command fstab"));
+    }
+    if color
+        && !matches!(
+            command,
+            Command::Diff { .. } | Command::Fstab { diff: true, .. }
+        )
+    {
+        return Err(Error::msg(
+            "--color can only be used with command diff or fstab --diff",
+        ));
+    }
+
+    let sysfs = match (sysfs, dev_root) {
+        (Some(sysfs), Some(dev_root)) => Some(sysfs.with_dev_root(dev_root)),
+        (None, Some(_)) => {
+            return Err(Error::msg(
+                "--dev-root can only be used with --sysfs or --sysfs-root",
+            ));
+        }
+        (sysfs, None) => sysfs,
+    };
+
+    Ok(Args {
+        input,
+        image,
+        output,
+        command,
+        arrangement: Arrangement::new(sort_by.unwrap_or_default(), pins, numbering),
+        sysfs,
+    })
+}
+
+fn flag_value(flag: &str, value: Option<String>) -> Result<String> {
+    match value {
+        Some(value) => Ok(value),
+        None => Err(Error::msg(format!("missing value for {}", flag))),
+    }
+}
+
+/// Parses the value of a flag taking a partition number, which starts at 1.
+fn designation_value(flag: &str, value: Option<String>) -> Result<usize> {
+    let value = flag_value(flag, value)?;
+    match value.parse::<usize>() {
+        Ok(designation) if designation > 0 => Ok(designation),
+        _ => Err(Error::msg(format!(
+            "invalid partition number {} for {}",
+            value, flag
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod cli_test {
+    use super::{
+        parse, Alignment, Arrangement, Args, Command, MappingFormat, Numbering, Pin,
+        SortKey, Sysfs,
+    };
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse(args("")).unwrap(),
+            Args {
+                input: None,
+                image: None,
+                output: None,
+                command: Command::Sort { mapping: None },
+                arrangement: Arrangement::default(),
+                sysfs: None,
+            }
+        );
+        assert_eq!(
+            parse(args("-i in.txt sort --mapping json --output out.json")).unwrap(),
+            Args {
+                input: Some(String::from("in.txt")),
+                image: None,
+                output: Some(String::from("out.json")),
+                command: Command::Sort {
+                    mapping: Some(MappingFormat::Json)
+                },
+                arrangement: Arrangement::default(),
+                sysfs: None,
+            }
+        );
+        assert_eq!(
+            parse(args("--mapping sort")).unwrap().command,
+            Command::Sort {
+                mapping: Some(MappingFormat::Table)
+            }
+        );
+        assert_eq!(
+            parse(args("validate")).unwrap().command,
+            Command::Validate { align: None }
+        );
+        assert_eq!(
+            parse(args("validate --align grain")).unwrap().command,
+            Command::Validate {
+                align: Some(Alignment::Grain)
+            }
+        );
+        assert_eq!(
+            parse(args("align")).unwrap().command,
+            Command::Align {
+                align: Alignment::Bytes(1048576)
+            }
+        );
+        assert_eq!(parse(args("--check")).unwrap().command, Command::Check);
+        assert_eq!(parse(args("sort --check")).unwrap().command, Command::Check);
+        assert_eq!(parse(args("info")).unwrap().command, Command::Info);
+        assert_eq!(parse(args("free")).unwrap().command, Command::Free);
+        assert_eq!(
+            parse(args("diff")).unwrap().command,
+            Command::Diff { color: false }
+        );
+        assert_eq!(
+            parse(args("diff --color")).unwrap().command,
+            Command::Diff { color: true }
+        );
+        assert_eq!(
+            parse(args("fstab --diff /etc/fstab")).unwrap().command,
+            Command::Fstab {
+                path: String::from("/etc/fstab"),
+                diff: true,
+                color: false,
+            }
+        );
+        assert_eq!(
+            parse(args("--check --sort-by type")).unwrap(),
+            Args {
+                input: None,
+                image: None,
+                output: None,
+                command: Command::Check,
+                arrangement: Arrangement::new(
+                    SortKey::TypeGroups,
+                    Vec::new(),
+                    Numbering::default(),
+                ),
+                sysfs: None,
+            }
+        );
+        assert_eq!(
+            parse(args("diff --pin /dev/mmcblk0p1=1 --pin label:boot=2"))
+                .unwrap()
+                .arrangement
+                .pins,
+            vec![
+                "/dev/mmcblk0p1=1".parse::<Pin>().unwrap(),
+                "label:boot=2".parse::<Pin>().unwrap(),
+            ]
+        );
+        assert_eq!(
+            parse(args("--start-at 2 --gap 4 --gap 5"))
+                .unwrap()
+                .arrangement
+                .numbering,
+            Numbering::Sequential {
+                first: 2,
+                gaps: vec![4, 5]
+            }
+        );
+        assert_eq!(
+            parse(args("--keep-numbers")).unwrap().arrangement.numbering,
+            Numbering::KeepUsed
+        );
+        assert_eq!(
+            parse(args("info --sysfs")).unwrap().sysfs,
+            Some(Sysfs::default())
+        );
+        assert_eq!(
+            parse(args("--sysfs-root /tmp/block --check")).unwrap().sysfs,
+            Some(Sysfs::new("/tmp/block"))
+        );
+        assert_eq!(
+            parse(args("--sysfs-root /tmp/block --dev-root /tmp/dev"))
+                .unwrap()
+                .sysfs,
+            Some(Sysfs::new("/tmp/block").with_dev_root("/tmp/dev"))
+        );
+        assert_eq!(
+            parse(args("--image disk.img info")).unwrap().image.as_deref(),
+            Some("disk.img")
+        );
+        assert_eq!(
+            parse(args("--image disk.img write --dry-run --sort-by type"))
+                .unwrap()
+                .command,
+            Command::Write {
+                path: String::from("disk.img"),
+                dry_run: true,
+            }
+        );
+        assert_eq!(parse(args("info --help")).unwrap().command, Command::Help);
+        assert_eq!(parse(args("-V")).unwrap().command, Command::Version);
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        for invalid in [
+            "--bogus",
+            "frobnicate",
+            "fstab",
+            "--input",
+            "--sysfs-root",
+            "--dev-root /tmp/dev",
+            "--image",
+            "-i in.txt --image disk.img",
+            "write",
+            "--image disk.img sort --dry-run",
+            "info --mapping",
+            "diff --diff",
+            "diff --check",
+            "--check --mapping",
+            "info --color",
+            "fstab --color /etc/fstab",
+            "--sort-by",
+            "--sort-by size",
+            "info --sort-by type",
+            "--pin /dev/sda1",
+            "validate --pin /dev/sda1=1",
+            "--start-at 0",
+            "--gap x",
+            "--keep-numbers --start-at 2",
+            "info --keep-numbers",
+            "free --pin /dev/sda1=1",
+            "align --sort-by type",
+            "--align 4K",
+            "validate --align 1T",
+        ] {
+            assert!(
+                parse(args(invalid)).is_err(),
+                "{} should be rejected",
+                invalid
+            );
+        }
+    }
+}
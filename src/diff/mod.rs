@@ -0,0 +1,289 @@
+//! Line diffs of a dump before and after sorting, printed like `diff -u`.
+//!
+//! `edits` compares two texts line by line, `hunks` groups the changes with the lines
+//! around them, and `unified` does both and formats the result, optionally in color and
+//! with a note after each changed line:
+//!
+//! ```
+//! use sfdisk_sort_rs::diff::{self, Style};
+//!
+//! let old = "/dev/sda1 : start=4096\n/dev/sda2 : start=2048\n";
+//! let new = "/dev/sda1 : start=2048\n/dev/sda2 : start=4096\n";
+//! let text = diff::unified(old, new, "a/sda", "b/sda", diff::DEFAULT_CONTEXT, Style::default());
+//!
+//! assert!(text.starts_with("--- a/sda\n+++ b/sda\n@@ -1,2 +1,2 @@\n"));
+//! ```
+//!
+//! The `diff` and `fstab` commands print their changes this way, and `report::designation_note`
+//! supplies the notes with the old and new partition numbers.
+
+/// Number of unchanged lines printed around each change, like `diff -u`.
+pub const DEFAULT_CONTEXT: usize = 3;
+
+/// One line of a line-oriented diff.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Edit<'a> {
+    /// A line in both texts
+    Equal(&'a str),
+    /// A line only in the old text
+    Delete(&'a str),
+    /// A line only in the new text
+    Insert(&'a str),
+}
+
+/// Computes the line edits turning `old` into `new`, using the longest common subsequence.
+pub fn edits<'a>(old: &'a str, new: &'a str) -> Vec<Edit<'a>> {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+
+    // lcs[i][j] is the LCS length of a[i..] and b[j..]
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut result = Vec::with_capacity(a.len().max(b.len()));
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            result.push(Edit::Equal(a[i]));
+            i += 1;
+            j += 1;
+        } else if j >= b.len() || (i < a.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            result.push(Edit::Delete(a[i]));
+            i += 1;
+        } else {
+            result.push(Edit::Insert(b[j]));
+            j += 1;
+        }
+    }
+
+    result
+}
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const CYAN: &str = "\x1b[36m";
+
+/// Returns a note to print after a removed or added line, if any.
+pub type Annotate<'a> = &'a dyn Fn(&Edit) -> Option<String>;
+
+/// How `unified` prints the diff.
+#[derive(Default, Clone, Copy)]
+pub struct Style<'a> {
+    /// Colors file headers bold, hunk headers cyan, removed lines red and added lines green
+    pub color: bool,
+    /// Appends a note to removed and added lines, e.g. their old and new designations
+    pub annotate: Option<Annotate<'a>>,
+}
+
+/// Formats the changes between `old` and `new` as a unified diff with `context` lines
+/// around each change, styled by `style`. Returns an empty string if the texts have the same lines.
+pub fn unified(
+    old: &str,
+    new: &str,
+    old_label: &str,
+    new_label: &str,
+    context: usize,
+    style: Style,
+) -> String {
+    let edits = edits(old, new);
+    let hunks = hunks(&edits, context);
+    if hunks.is_empty() {
+        return String::new();
+    }
+
+    let paint = |color: &str, text: String| -> String {
+        if style.color {
+            format!("{}{}{}", color, text, RESET)
+        } else {
+            text
+        }
+    };
+
+    let mut out = String::new();
+    out.push_str(&paint(BOLD, format!("--- {}", old_label)));
+    out.push('\n');
+    out.push_str(&paint(BOLD, format!("+++ {}", new_label)));
+    out.push('\n');
+    for hunk in hunks {
+        out.push_str(&paint(CYAN, hunk.header()));
+        out.push('\n');
+        for edit in &edits[hunk.edits.clone()] {
+            let note = match (edit, style.annotate) {
+                (Edit::Equal(_), _) | (_, None) => None,
+                (_, Some(annotate)) => annotate(edit),
+            };
+            let (color, mut line) = match edit {
+                Edit::Equal(line) => ("", format!(" {}", line)),
+                Edit::Delete(line) => (RED, format!("-{}", line)),
+                Edit::Insert(line) => (GREEN, format!("+{}", line)),
+            };
+            if let Some(note) = note {
+                line.push_str("  ");
+                line.push_str(&note);
+            }
+            if color.is_empty() {
+                out.push_str(&line);
+            } else {
+                out.push_str(&paint(color, line));
+            }
+            out.push('\n');
+        }
+    }
+
+    out
+}
+
+/// A group of nearby edits, with the 1-based line ranges it covers in both texts.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Hunk {
+    /// Indices of the edits in the hunk, context lines included
+    pub edits: std::ops::Range<usize>,
+    /// First line of the hunk in the old text
+    pub old_start: usize,
+    /// Number of old text lines in the hunk
+    pub old_len: usize,
+    /// First line of the hunk in the new text
+    pub new_start: usize,
+    /// Number of new text lines in the hunk
+    pub new_len: usize,
+}
+
+impl Hunk {
+    /// Formats the `@@ -1,4 +1,4 @@` hunk header. Empty ranges start at the line before them.
+    pub fn header(&self) -> String {
+        let old_start = if self.old_len == 0 {
+            self.old_start - 1
+        } else {
+            self.old_start
+        };
+        let new_start = if self.new_len == 0 {
+            self.new_start - 1
+        } else {
+            self.new_start
+        };
+
+        format!(
+            "@@ -{},{} +{},{} @@",
+            old_start, self.old_len, new_start, self.new_len
+        )
+    }
+}
+
+/// Groups `edits` into hunks, merging changes that are at most `2 * context` lines apart.
+pub fn hunks(edits: &[Edit], context: usize) -> Vec<Hunk> {
+    let changes: Vec<usize> = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, edit)| !matches!(edit, Edit::Equal(_)))
+        .map(|(i, _)| i)
+        .collect();
+
+    let mut ranges: Vec<std::ops::Range<usize>> = Vec::new();
+    for i in changes {
+        let start = i.saturating_sub(context);
+        let end = (i + 1 + context).min(edits.len());
+        match ranges.last_mut() {
+            Some(last) if start <= last.end => last.end = end,
+            _ => ranges.push(start..end),
+        }
+    }
+
+    ranges
+        .into_iter()
+        .map(|range| {
+            // Line numbers of the first edit in the hunk
+            let before = &edits[..range.start];
+            let old_start =
+                1 + before.iter().filter(|e| !matches!(e, Edit::Insert(_))).count();
+            let new_start =
+                1 + before.iter().filter(|e| !matches!(e, Edit::Delete(_))).count();
+
+            let within = &edits[range.clone()];
+            Hunk {
+                old_start,
+                old_len: within.iter().filter(|e| !matches!(e, Edit::Insert(_))).count(),
+                new_start,
+                new_len: within.iter().filter(|e| !matches!(e, Edit::Delete(_))).count(),
+                edits: range,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod diff_test {
+    use super::{edits, unified, Edit, Style};
+
+    #[test]
+    fn test_edits() {
+        assert_eq!(
+            edits("a\nb\nc\n", "a\nc\nd\n"),
+            vec![
+                Edit::Equal("a"),
+                Edit::Delete("b"),
+                Edit::Equal("c"),
+                Edit::Insert("d"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unified() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n";
+        let new = "1\n2\nthree\n4\n5\n6\n7\n8\n9\n10\n11\n12\n13\n";
+
+        assert_eq!(
+            unified(old, new, "a", "b", 1, Style::default()),
+            "--- a
++++ b
+@@ -2,3 +2,3 @@
+ 2
+-3
++three
+ 4
+@@ -12,1 +12,2 @@
+ 12
++13
+"
+        );
+
+        assert_eq!(unified(old, old, "a", "b", 3, Style::default()), "");
+        assert_eq!(
+            unified("", "new\n", "a", "b", 3, Style::default()),
+            "--- a\n+++ b\n@@ -0,0 +1,1 @@\n+new\n"
+        );
+    }
+
+    #[test]
+    fn test_unified_style() {
+        let annotate = |edit: &Edit| match edit {
+            Edit::Delete(line) if *line == "b" => Some(String::from("# was b")),
+            _ => None,
+        };
+        let style = Style {
+            color: true,
+            annotate: Some(&annotate),
+        };
+
+        assert_eq!(
+            unified("a\nb\n", "a\nc\n", "x", "y", 3, style),
+            "\x1b[1m--- x\x1b[0m
+\x1b[1m+++ y\x1b[0m
+\x1b[36m@@ -1,2 +1,2 @@\x1b[0m
+ a
+\x1b[31m-b  # was b\x1b[0m
+\x1b[32m+c\x1b[0m
+"
+        );
+    }
+}
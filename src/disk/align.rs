@@ -0,0 +1,271 @@
+use super::free::DEFAULT_GRAIN;
+use super::validate::Diagnostic;
+use super::Disk;
+use crate::error::LayoutError;
+
+/// Boundary that partition starts and sizes should be multiples of.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Alignment {
+    /// A fixed number of bytes, 1 MiB by default
+    Bytes(u64),
+    /// The `grain:` of the dump, or 1 MiB if it has none
+    Grain,
+}
+
+impl Default for Alignment {
+    fn default() -> Self {
+        Self::Bytes(DEFAULT_GRAIN)
+    }
+}
+
+impl Alignment {
+    /// Returns the boundary in sectors of `disk`. A size in bytes must be a whole number
+    /// of sectors, e.g. 1000 bytes cannot be a boundary of 512-byte sectors, and the
+    /// sectors cannot be empty.
+    pub fn sectors(&self, disk: &Disk) -> Result<u64, LayoutError> {
+        if disk.sector_size() == 0 {
+            return Err(LayoutError::InvalidSectorSize {
+                device: disk.name.clone(),
+                sector_size: 0,
+            });
+        }
+
+        match self {
+            Self::Bytes(bytes) if bytes % disk.sector_size() != 0 => {
+                Err(LayoutError::PartialSectorAlignment {
+                    device: disk.name.clone(),
+                    bytes: *bytes,
+                    sector_size: disk.sector_size(),
+                })
+            }
+            Self::Bytes(bytes) => Ok(bytes / disk.sector_size()),
+            Self::Grain => Ok(disk.grain_sectors()),
+        }
+    }
+}
+
+/// Parses the `--align` values `grain`, or a size in bytes with an optional binary
+/// `K`, `M` or `G` suffix, e.g. `4096` or `1M`.
+impl std::str::FromStr for Alignment {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        if s == "grain" {
+            return Ok(Self::Grain);
+        }
+
+        let (number, multiplier) = match s.char_indices().last() {
+            Some((i, 'K' | 'k')) => (&s[..i], 1024),
+            Some((i, 'M' | 'm')) => (&s[..i], 1024 * 1024),
+            Some((i, 'G' | 'g')) => (&s[..i], 1024 * 1024 * 1024),
+            _ => (s, 1),
+        };
+        match number
+            .parse::<u64>()
+            .ok()
+            .and_then(|n| n.checked_mul(multiplier))
+        {
+            Some(bytes) if bytes > 0 => Ok(Self::Bytes(bytes)),
+            _ => Err(format!(
+                "invalid alignment {}, expected grain or a size like 4096 or 1M",
+                s
+            )),
+        }
+    }
+}
+
+/// A partition whose start or size is not a multiple of the alignment boundary.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Misalignment {
+    /// Name of the misaligned partition
+    pub partition: String,
+    /// Boundary in sectors
+    pub boundary: u64,
+    /// Start block of the partition
+    pub start: u64,
+    /// Sectors past the last boundary before `start`
+    pub start_offset: u64,
+    /// Sectors past the last multiple of the boundary in the size
+    pub size_remainder: u64,
+    /// The first boundary at or after `start`, if there is one before the largest block
+    /// number
+    pub suggested_start: Option<u64>,
+}
+
+impl std::fmt::Display for Misalignment {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "partition {} is not aligned to {} sectors:",
+            self.partition, self.boundary
+        )?;
+        if self.start_offset > 0 {
+            write!(
+                f,
+                " start {} is {} sector(s) past a boundary",
+                self.start, self.start_offset
+            )?;
+            if let Some(suggested_start) = self.suggested_start {
+                write!(f, ", suggested start {}", suggested_start)?;
+            }
+        }
+        if self.start_offset > 0 && self.size_remainder > 0 {
+            write!(f, ", and")?;
+        }
+        if self.size_remainder > 0 {
+            write!(f, " size is {} sector(s) too long", self.size_remainder)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Disk {
+    /// Lists the partitions whose start or size is not aligned to `alignment`.
+    /// DOS extended partitions are skipped, as only their logicals hold data.
+    pub fn misalignments(
+        &self,
+        alignment: &Alignment,
+    ) -> Result<Vec<Misalignment>, LayoutError> {
+        let boundary = alignment.sectors(self)?;
+
+        let misalignments = self
+            .partitions
+            .iter()
+            .filter(|part| !part.is_extended())
+            .filter_map(|part| {
+                let start_offset = part.start_block % boundary;
+                let size_remainder = part.size.unwrap_or(0) % boundary;
+                if start_offset == 0 && size_remainder == 0 {
+                    return None;
+                }
+
+                let suggested_start = match start_offset {
+                    0 => Some(part.start_block),
+                    _ => (part.start_block - start_offset).checked_add(boundary),
+                };
+                Some(Misalignment {
+                    partition: part.name.clone(),
+                    boundary,
+                    start: part.start_block,
+                    start_offset,
+                    size_remainder,
+                    suggested_start,
+                })
+            })
+            .collect();
+
+        Ok(misalignments)
+    }
+
+    /// Like `validate`, also reporting misaligned partitions as problems.
+    pub fn validate_aligned(
+        &self,
+        alignment: &Alignment,
+    ) -> Result<Vec<Diagnostic>, LayoutError> {
+        let mut diagnostics = self.validate();
+        diagnostics.extend(
+            self.misalignments(alignment)?
+                .into_iter()
+                .map(Diagnostic::Misaligned),
+        );
+
+        Ok(diagnostics)
+    }
+}
+
+#[cfg(test)]
+mod align_test {
+    use super::{Alignment, Misalignment};
+    use crate::disk::header::DiskHeader;
+    use crate::disk::validate::Diagnostic;
+    use crate::disk::{parse_sfdisk_full_disk, Disk};
+    use crate::error::LayoutError;
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!("grain".parse(), Ok(Alignment::Grain));
+        assert_eq!("4096".parse(), Ok(Alignment::Bytes(4096)));
+        assert_eq!("1M".parse(), Ok(Alignment::Bytes(1048576)));
+        for invalid in ["", "0", "M", "1T", "-1K", "17179869184G"] {
+            assert!(
+                invalid.parse::<Alignment>().is_err(),
+                "{} should be rejected",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_misalignments() {
+        let input = "label: gpt
+device: /dev/sda
+grain: 4096
+sector-size: 512
+
+/dev/sda1 : start=        2048, size=        2048
+/dev/sda2 : start=        4104, size=        4088
+";
+        let disk = parse_sfdisk_full_disk(String::from(input)).unwrap();
+        let misaligned = Misalignment {
+            partition: String::from("/dev/sda2"),
+            boundary: 2048,
+            start: 4104,
+            start_offset: 8,
+            size_remainder: 2040,
+            suggested_start: Some(6144),
+        };
+
+        assert_eq!(
+            disk.misalignments(&Alignment::default()),
+            Ok(vec![misaligned.clone()])
+        );
+        assert_eq!(
+            misaligned.to_string(),
+            "partition /dev/sda2 is not aligned to 2048 sectors: start 4104 is 8 sector(s) \
+             past a boundary, suggested start 6144, and size is 2040 sector(s) too long"
+        );
+        assert_eq!(
+            disk.validate_aligned(&Alignment::default()),
+            Ok(vec![Diagnostic::Misaligned(misaligned)])
+        );
+
+        // 4096 bytes of grain are 8 sectors
+        assert_eq!(disk.misalignments(&Alignment::Grain), Ok(vec![]));
+        assert_eq!(disk.validate(), vec![]);
+
+        assert_eq!(
+            disk.misalignments(&Alignment::Bytes(1000)),
+            Err(LayoutError::PartialSectorAlignment {
+                device: String::from("/dev/sda"),
+                bytes: 1000,
+                sector_size: 512,
+            })
+        );
+    }
+
+    #[test]
+    fn test_zero_sector_size() {
+        let header = DiskHeader {
+            device: String::from("/dev/sda"),
+            sector_size: Some(0),
+            ..Default::default()
+        };
+        let disk = Disk::new(header, vec![]).unwrap();
+
+        for alignment in [Alignment::default(), Alignment::Grain] {
+            assert_eq!(
+                disk.misalignments(&alignment),
+                Err(LayoutError::InvalidSectorSize {
+                    device: String::from("/dev/sda"),
+                    sector_size: 0,
+                })
+            );
+        }
+        assert_eq!(disk.grain_sectors(), 1);
+    }
+}
@@ -0,0 +1,147 @@
+use crate::error::LayoutError;
+use crate::partition::Partition;
+use super::sort::SortKey;
+
+/// Highest designation a primary (or extended) partition can have on a DOS disk.
+pub const MAX_PRIMARY: usize = 4;
+/// Designation of the first logical partition inside the extended container.
+pub const FIRST_LOGICAL: usize = 5;
+
+/// Orders `partitions` the way a DOS partition table numbers them, and returns
+/// the new designation for each partition (same order as the sorted `partitions`).
+///
+/// Primaries (including the extended container) are sorted by `key` into 1-4,
+/// and logicals inside the extended container are sorted by `key` from 5 upward.
+pub fn arrange(
+    partitions: &mut Vec<Partition>,
+    key: &SortKey,
+) -> Result<Vec<usize>, LayoutError> {
+    let extendeds: Vec<&Partition> =
+        partitions.iter().filter(|part| part.is_extended()).collect();
+
+    let container = match extendeds.as_slice() {
+        [] => None,
+        [extended] => match extended.end_block() {
+            Some(end) => Some((extended.start_block, end)),
+            None => {
+                return Err(LayoutError::ExtendedWithoutSize(extended.name.clone()));
+            }
+        },
+        _ => {
+            let names = extendeds.iter().map(|part| part.name.clone()).collect();
+            return Err(LayoutError::TooManyExtended(names));
+        }
+    };
+
+    // Check the layout before touching partitions, so that errors leave them intact
+    let primaries: Vec<&Partition> = partitions
+        .iter()
+        .filter(|part| !is_logical(part, container))
+        .collect();
+    for part in &primaries {
+        if part.designation >= FIRST_LOGICAL {
+            return Err(LayoutError::LogicalOutsideExtended(part.name.clone()));
+        }
+    }
+    if primaries.len() > MAX_PRIMARY {
+        return Err(LayoutError::TooManyPrimaries {
+            max: MAX_PRIMARY,
+            partitions: primaries.iter().map(|part| part.name.clone()).collect(),
+        });
+    }
+
+    let (mut logicals, mut primaries): (Vec<Partition>, Vec<Partition>) = partitions
+        .drain(..)
+        .partition(|part| is_logical(part, container));
+
+    primaries.sort_by(|a, b| key.compare(a, b));
+    logicals.sort_by(|a, b| key.compare(a, b));
+
+    let designations = (1..=primaries.len())
+        .chain(FIRST_LOGICAL..FIRST_LOGICAL + logicals.len())
+        .collect();
+
+    partitions.append(&mut primaries);
+    partitions.append(&mut logicals);
+
+    Ok(designations)
+}
+
+/// Reports whether `part` lies inside the extended container `(start, end)`.
+fn is_logical(part: &Partition, container: Option<(u64, u64)>) -> bool {
+    let (start, end) = match container {
+        Some(container) => container,
+        None => return false,
+    };
+
+    !part.is_extended()
+        && part.start_block > start
+        && part.end_block().unwrap_or(part.start_block) <= end
+}
+
+#[cfg(test)]
+mod dos_test {
+    use super::arrange;
+    use crate::disk::sort::SortKey;
+    use crate::error::LayoutError;
+    use crate::linux::block;
+    use crate::partition::{Partition, PartitionType};
+
+    fn dos_part(designation: usize, start: u64, size: u64, code: u8) -> Partition {
+        let mut part = Partition::new_from_start_block(
+            designation,
+            start,
+            block::LinuxBlockDevice::SCSI,
+        );
+        part.size = Some(size);
+        part.part_type = Some(PartitionType::Mbr(code));
+
+        part
+    }
+
+    #[test]
+    fn test_arrange() {
+        let mut parts = vec![
+            dos_part(6, 30000, 5000, 0x83),  // logical
+            dos_part(1, 50000, 1000, 0x83),  // primary
+            dos_part(2, 20000, 20000, 0x05), // extended
+            dos_part(5, 22000, 5000, 0x82),  // logical
+            dos_part(3, 2048, 10000, 0x83),  // primary
+        ];
+
+        let designations = arrange(&mut parts, &SortKey::StartBlock).unwrap();
+        let starts: Vec<u64> = parts.iter().map(|part| part.start_block).collect();
+
+        assert_eq!(starts, vec![2048, 20000, 50000, 22000, 30000]);
+        assert_eq!(designations, vec![1, 2, 3, 5, 6]);
+    }
+
+    #[test]
+    fn test_arrange_invalid() {
+        let mut too_many_primaries = vec![
+            dos_part(1, 2048, 100, 0x83),
+            dos_part(2, 4096, 100, 0x83),
+            dos_part(3, 6144, 100, 0x83),
+            dos_part(4, 8192, 100, 0x83),
+            dos_part(4, 10240, 100, 0x83),
+        ];
+        assert!(arrange(&mut too_many_primaries, &SortKey::StartBlock).is_err());
+
+        let mut two_extendeds =
+            vec![dos_part(1, 2048, 100, 0x05), dos_part(2, 4096, 100, 0x0f)];
+        assert!(arrange(&mut two_extendeds, &SortKey::StartBlock).is_err());
+
+        let mut stray_logical = vec![
+            dos_part(1, 2048, 1000, 0x05),
+            dos_part(5, 2100, 100, 0x83),
+            dos_part(6, 9000, 100, 0x83),
+        ];
+        assert_eq!(
+            arrange(&mut stray_logical, &SortKey::StartBlock),
+            Err(LayoutError::LogicalOutsideExtended(String::from(
+                "/dev/sda6"
+            )))
+        );
+        assert_eq!(stray_logical.len(), 3);
+    }
+}
@@ -0,0 +1,190 @@
+use super::header::Label;
+use super::Disk;
+
+/// Sector size assumed when the dump has no `sector-size:` line.
+pub const DEFAULT_SECTOR_SIZE: u64 = 512;
+/// Alignment (in bytes) assumed when the dump has no `grain:` line, the sfdisk default.
+pub const DEFAULT_GRAIN: u64 = 1024 * 1024;
+
+/// A run of unallocated sectors, from `start` to `end` inclusive.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct FreeSpace {
+    /// First free sector
+    pub start: u64,
+    /// Last free sector
+    pub end: u64,
+}
+
+impl FreeSpace {
+    /// Returns the length of the gap, saturating at `u64::MAX` for a gap of every sector.
+    pub fn sectors(&self) -> u64 {
+        self.end.saturating_sub(self.start).saturating_add(1)
+    }
+}
+
+impl Disk {
+    /// Returns the `sector-size:` of the dump, or 512 bytes.
+    pub fn sector_size(&self) -> u64 {
+        self.header.sector_size.unwrap_or(DEFAULT_SECTOR_SIZE)
+    }
+
+    /// Returns the `grain:` of the dump in sectors, or 1 MiB worth of sectors. At least
+    /// 1, even for a disk built with a sector size of 0.
+    pub fn grain_sectors(&self) -> u64 {
+        let grain = self.header.grain.unwrap_or(DEFAULT_GRAIN);
+        grain.checked_div(self.sector_size()).unwrap_or_default().max(1)
+    }
+
+    /// Lists the unallocated gaps between `first-lba` and `last-lba`, like `sfdisk -F`.
+    ///
+    /// Without `first-lba`, free space starts after the partition table (sector 34 on GPT,
+    /// 1 on DOS). Without `last-lba`, the size of the disk is unknown, so the space after
+    /// the last partition is not listed. Space inside a DOS extended partition that no
+    /// logical partition uses counts as free.
+    pub fn free_space(&self) -> Vec<FreeSpace> {
+        let first = self.header.first_lba.unwrap_or(match self.header.label {
+            Some(Label::Dos) => 1,
+            _ => 34,
+        });
+        let last = self.header.last_lba;
+
+        let mut used: Vec<(u64, Option<u64>)> = self
+            .partitions
+            .iter()
+            .filter(|part| !part.is_extended() && part.size != Some(0))
+            .map(|part| (part.start_block, part.end_block()))
+            .collect();
+        used.sort_unstable();
+
+        let mut gaps: Vec<FreeSpace> = Vec::new();
+        let mut cursor = Some(first);
+        for (start, end) in used {
+            let from = match cursor {
+                Some(from) => from,
+                None => break,
+            };
+            if start > from {
+                let end = match last {
+                    Some(last) => (start - 1).min(last),
+                    None => start - 1,
+                };
+                if end >= from {
+                    gaps.push(FreeSpace { start: from, end });
+                }
+            }
+            // Partitions without size, or ending in the last sector, take the rest of
+            // the disk
+            cursor = end
+                .and_then(|end| end.checked_add(1))
+                .map(|next| from.max(next));
+        }
+
+        if let (Some(from), Some(last)) = (cursor, last) {
+            if from <= last {
+                gaps.push(FreeSpace {
+                    start: from,
+                    end: last,
+                });
+            }
+        }
+
+        gaps
+    }
+}
+
+#[cfg(test)]
+mod free_test {
+    use super::FreeSpace;
+    use crate::disk::parse_sfdisk_full_disk;
+
+    #[test]
+    fn test_free_space() {
+        let input = "label: gpt
+device: /dev/sda
+first-lba: 34
+last-lba: 100000
+sector-size: 4096
+grain: 8192
+
+/dev/sda1 : start=        2048, size=        2048
+/dev/sda2 : start=       10000, size=        1000
+/dev/sda3 : start=        3000, size=        1000
+";
+        let disk = parse_sfdisk_full_disk(String::from(input)).unwrap();
+
+        assert_eq!(disk.grain_sectors(), 2);
+        assert_eq!(
+            disk.free_space(),
+            vec![
+                FreeSpace {
+                    start: 34,
+                    end: 2047,
+                },
+                FreeSpace {
+                    start: 4096,
+                    end: 9999,
+                },
+                FreeSpace {
+                    start: 11000,
+                    end: 100000,
+                },
+            ]
+        );
+        assert_eq!(disk.free_space()[1].sectors(), 5904);
+    }
+
+    #[test]
+    fn test_free_space_dos() {
+        let input = std::fs::read_to_string("./assets/sfdisk_output_dos_ugly.txt")
+            .expect("failed to read ugly DOS test text file");
+        let disk = parse_sfdisk_full_disk(input).unwrap();
+
+        // The gaps in the extended container hold the logical partition tables, and
+        // without last-lba nothing is listed after the last partition
+        assert_eq!(
+            disk.free_space(),
+            vec![
+                FreeSpace {
+                    start: 1,
+                    end: 2047,
+                },
+                FreeSpace {
+                    start: 1050624,
+                    end: 1052671,
+                },
+                FreeSpace {
+                    start: 20971520,
+                    end: 20973567,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_free_space_limits() {
+        // A partition ending in the last addressable sector leaves nothing after it
+        let input = "label: gpt
+device: /dev/sda
+last-lba: 18446744073709551615
+
+/dev/sda1 : start=        2048, size=18446744073709549568
+";
+        let disk = parse_sfdisk_full_disk(String::from(input)).unwrap();
+        assert_eq!(disk.partitions[0].end_block(), Some(u64::MAX));
+        assert_eq!(
+            disk.free_space(),
+            vec![FreeSpace {
+                start: 34,
+                end: 2047,
+            }]
+        );
+
+        let whole = FreeSpace {
+            start: 0,
+            end: u64::MAX,
+        };
+        assert_eq!(whole.sectors(), u64::MAX);
+    }
+}
@@ -0,0 +1,300 @@
+use crate::error::{ParseError, Span};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_json::Value;
+
+const SFDISK_HEADER_LINE_PATTERN: &str = r"^(?P<key>[a-z][a-z-]*):\s*(?P<value>.*?)\s*$";
+const SFDISK_DEVICE_NAME_PATTERN: &str = r"^\S+$";
+
+lazy_static! {
+    static ref SFDISK_HEADER_LINE_REGEX: Regex =
+        Regex::new(SFDISK_HEADER_LINE_PATTERN).unwrap();
+    static ref SFDISK_DEVICE_NAME_REGEX: Regex =
+        Regex::new(SFDISK_DEVICE_NAME_PATTERN).unwrap();
+}
+
+/// Number of GPT partition entries sfdisk assumes when the dump has no `table-length:`.
+pub const DEFAULT_TABLE_LENGTH: u64 = 128;
+
+/// Partition table type from the `label:` header line.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+#[non_exhaustive]
+pub enum Label {
+    /// GUID Partition Table
+    Gpt,
+    /// MBR partition table, with primary, extended and logical partitions
+    Dos,
+}
+
+impl std::fmt::Display for Label {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Gpt => write!(f, "gpt"),
+            Self::Dos => write!(f, "dos"),
+        }
+    }
+}
+
+impl std::str::FromStr for Label {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, ParseError> {
+        match s {
+            "gpt" => Ok(Self::Gpt),
+            "dos" => Ok(Self::Dos),
+            _ => Err(ParseError::UnknownLabel(Span::new(0, 0..s.len(), s))),
+        }
+    }
+}
+
+/// Typed model of the header lines of `sfdisk -d` output, e.g. `label: gpt` or `first-lba: 2048`.
+/// Numbers are in sectors, except `grain` which is in bytes.
+#[derive(Default, Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct DiskHeader {
+    /// `label:`, the partition table type
+    pub label: Option<Label>,
+    /// `label-id:`, the disk GUID on GPT or the disk identifier on DOS
+    pub label_id: Option<String>,
+    /// `device:`, the disk the dump is of, e.g. `/dev/sda`
+    pub device: String,
+    /// `unit:`, always `sectors` in dumps of current sfdisk
+    pub unit: Option<String>,
+    /// `first-lba:`, the first sector partitions can use
+    pub first_lba: Option<u64>,
+    /// `last-lba:`, the last sector partitions can use
+    pub last_lba: Option<u64>,
+    /// `table-length:`, the number of GPT partition entries
+    pub table_length: Option<u64>,
+    /// `grain:`, the alignment sfdisk uses for new partitions, in bytes
+    pub grain: Option<u64>,
+    /// `sector-size:`, in bytes
+    pub sector_size: Option<u64>,
+    /// Header fields with unknown keys, kept in input order for round-tripping.
+    /// Values of text dumps are strings, values of JSON dumps keep their JSON type.
+    pub extras: Vec<(String, Value)>,
+}
+
+/// Reports whether `s` looks like a `key: value` header line of `sfdisk -d` output.
+pub fn is_sfdisk_header_line(s: &str) -> bool {
+    SFDISK_HEADER_LINE_REGEX.is_match(s)
+}
+
+impl DiskHeader {
+    /// Returns the highest partition number the table has room for: the `table-length:`
+    /// of the dump, or 128 on GPT. DOS disks are bounded by `dos::arrange` instead.
+    pub fn last_designation(&self) -> Option<usize> {
+        match (self.table_length, self.label) {
+            (Some(table_length), _) => usize::try_from(table_length).ok(),
+            (None, Some(Label::Gpt)) => Some(DEFAULT_TABLE_LENGTH as usize),
+            _ => None,
+        }
+    }
+
+    /// Parses one `key: value` header line into the matching field.
+    /// Error spans point into `s`, with line number 0.
+    pub fn parse_line(&mut self, s: &str) -> Result<(), ParseError> {
+        self.parse_key_value(s).map(|_| ())
+    }
+
+    /// Like `parse_line`, and returns the key and where its value is in `s`.
+    pub(crate) fn parse_key_value<'a>(
+        &mut self,
+        s: &'a str,
+    ) -> Result<(&'a str, Span), ParseError> {
+        let caps = match SFDISK_HEADER_LINE_REGEX.captures(s) {
+            Some(caps) => caps,
+            None => {
+                return Err(ParseError::UnrecognizedLine(Span::new(0, 0..s.len(), s)))
+            }
+        };
+        let key = caps.name("key").unwrap().as_str();
+        let value_match = caps.name("value").unwrap();
+        let value = value_match.as_str();
+        let span = Span::new(0, value_match.range(), s);
+
+        match key {
+            "label" => {
+                let label = value
+                    .parse()
+                    .map_err(|err: ParseError| err.within(span.columns.start, s))?;
+                self.label = Some(label);
+            }
+            "label-id" => self.label_id = Some(String::from(value)),
+            "device" => {
+                check_device(value, &span)?;
+                self.device = String::from(value);
+            }
+            "unit" => self.unit = Some(String::from(value)),
+            "first-lba" => self.first_lba = Some(parse_number(value, &span)?),
+            "last-lba" => self.last_lba = Some(parse_number(value, &span)?),
+            "table-length" => self.table_length = Some(parse_number(value, &span)?),
+            "grain" => self.grain = Some(parse_number(value, &span)?),
+            "sector-size" => self.sector_size = Some(parse_sector_size(value, &span)?),
+            _ => self.extras.push((String::from(key), Value::from(value))),
+        }
+
+        Ok((key, span))
+    }
+}
+
+fn parse_number(value: &str, span: &Span) -> Result<u64, ParseError> {
+    match str::parse::<u64>(value) {
+        Ok(num) => Ok(num),
+        Err(_) => Err(ParseError::InvalidNumber(span.clone())),
+    }
+}
+
+fn parse_sector_size(value: &str, span: &Span) -> Result<u64, ParseError> {
+    check_sector_size(parse_number(value, span)?, span)
+}
+
+/// Checks a sector size, which the kernel only supports as a power of two of at least 512.
+pub(crate) fn check_sector_size(
+    sector_size: u64,
+    span: &Span,
+) -> Result<u64, ParseError> {
+    if sector_size < 512 || !sector_size.is_power_of_two() {
+        return Err(ParseError::InvalidSectorSize(span.clone()));
+    }
+    Ok(sector_size)
+}
+
+/// Checks that a device name is a single word, as sfdisk prints it.
+pub(crate) fn check_device(value: &str, span: &Span) -> Result<(), ParseError> {
+    if !SFDISK_DEVICE_NAME_REGEX.is_match(value) {
+        return Err(ParseError::InvalidDevice(span.clone()));
+    }
+    Ok(())
+}
+
+/// Regenerates the header lines in the order `sfdisk -d` prints them.
+impl std::fmt::Display for DiskHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut lines: Vec<String> = Vec::new();
+
+        if let Some(label) = self.label {
+            lines.push(format!("label: {}", label));
+        }
+        if let Some(label_id) = &self.label_id {
+            lines.push(format!("label-id: {}", label_id));
+        }
+        lines.push(format!("device: {}", self.device));
+        if let Some(unit) = &self.unit {
+            lines.push(format!("unit: {}", unit));
+        }
+
+        let numbers = [
+            ("first-lba", self.first_lba),
+            ("last-lba", self.last_lba),
+            ("table-length", self.table_length),
+            ("grain", self.grain),
+            ("sector-size", self.sector_size),
+        ];
+        for (key, value) in numbers {
+            if let Some(value) = value {
+                lines.push(format!("{}: {}", key, value));
+            }
+        }
+
+        for (key, value) in &self.extras {
+            // Strings without their JSON quotes
+            match value {
+                Value::String(text) => lines.push(format!("{}: {}", key, text)),
+                _ => lines.push(format!("{}: {}", key, value)),
+            }
+        }
+
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod header_test {
+    use super::{is_sfdisk_header_line, DiskHeader, Label};
+    use crate::error::ParseError;
+
+    use serde_json::Value;
+
+    #[test]
+    fn test_device_name() {
+        let names = vec![
+            "device: /dev/sda",
+            "device: /dev/vdz",
+            "device: /dev/mmcblk2",
+            "device: /dev/nvme17n1",
+            // Disk images, as printed by `--image`
+            "device: disk.img",
+            "device: /var/lib/images/vm.raw",
+        ];
+
+        for name in names {
+            assert!(is_sfdisk_header_line(name));
+            let mut header = DiskHeader::default();
+            header.parse_line(name).unwrap();
+            assert_eq!(Some(header.device.as_str()), name.strip_prefix("device: "));
+        }
+
+        let mut header = DiskHeader::default();
+        assert!(header.parse_line("device: my disk.img").is_err());
+    }
+
+    #[test]
+    fn test_header() {
+        let text = "label: gpt
+label-id: 12345678-2345-6969-3264-A55555555555
+device: /dev/sda
+unit: sectors
+first-lba: 2048
+last-lba: 976773134
+table-length: 64
+grain: 1048576
+sector-size: 512
+foo-bar: baz";
+
+        let mut header = DiskHeader::default();
+        for line in text.lines() {
+            header.parse_line(line).unwrap();
+        }
+
+        assert_eq!(header.label, Some(Label::Gpt));
+        assert_eq!(header.first_lba, Some(2048));
+        assert_eq!(header.last_lba, Some(976773134));
+        assert_eq!(header.table_length, Some(64));
+        assert_eq!(header.grain, Some(1048576));
+        assert_eq!(header.sector_size, Some(512));
+        assert_eq!(
+            header.extras,
+            vec![(String::from("foo-bar"), Value::from("baz"))]
+        );
+        assert_eq!(header.to_string(), text);
+
+        let mut header = DiskHeader::default();
+        assert!(header.parse_line("label: sun").is_err());
+        let err = header.parse_line("first-lba: many").unwrap_err();
+        assert_eq!(err.span().snippet(), "many");
+        assert_eq!(err.span().columns, 11..15);
+    }
+
+    #[test]
+    fn test_sector_size() {
+        for size in ["512", "4096", "65536"] {
+            let mut header = DiskHeader::default();
+            header.parse_line(&format!("sector-size: {}", size)).unwrap();
+            assert_eq!(header.sector_size, Some(size.parse().unwrap()));
+        }
+
+        for size in ["0", "256", "520", "3072"] {
+            let mut header = DiskHeader::default();
+            let err = header
+                .parse_line(&format!("sector-size: {}", size))
+                .unwrap_err();
+            assert!(matches!(err, ParseError::InvalidSectorSize(_)), "{}", size);
+            assert_eq!(err.span().snippet(), size);
+        }
+    }
+}
@@ -0,0 +1,453 @@
+use super::header::{self, DiskHeader};
+use super::Disk;
+use crate::error::{NameSpans, ParseError, Span};
+use crate::partition::{self, token, Partition, PartitionType};
+
+use anyhow::{Error, Result, Context};
+use serde_json::value::RawValue;
+use serde_json::{json, Map, Value};
+
+use std::collections::HashMap;
+
+/// Reports whether the program input looks like `sfdisk -J` output rather than `sfdisk -d` output.
+pub fn is_sfdisk_json(prog_input: &str) -> bool {
+    prog_input.trim_start().starts_with('{')
+}
+
+/// Parses the `sfdisk -J` JSON output into Disk, the same way `parse_sfdisk_full_disk`
+/// does for `sfdisk -d` output. Errors point at the offending value.
+pub fn parse_sfdisk_json_disk(prog_input: String) -> Result<Disk, ParseError> {
+    let root: HashMap<String, &RawValue> = match serde_json::from_str(&prog_input) {
+        Ok(root) => root,
+        Err(err) => {
+            let text = prog_input
+                .lines()
+                .nth(err.line().saturating_sub(1))
+                .unwrap_or_default();
+            let column = err.column().saturating_sub(1).min(text.len());
+            let end = (column + 1).min(text.len());
+            let span = Span::new(err.line(), column..end, text);
+            return Err(ParseError::Json(span, err.to_string()));
+        }
+    };
+
+    let table = match root.get("partitiontable") {
+        Some(table) => table,
+        None => {
+            return Err(ParseError::Json(
+                Span::end_of(&prog_input),
+                String::from("missing object partitiontable"),
+            ))
+        }
+    };
+
+    let mut header = DiskHeader::default();
+    let mut partitions: Vec<Partition> = Vec::new();
+    let mut spans = NameSpans::default();
+
+    for (key, value) in object_fields(&prog_input, table, "partitiontable")? {
+        let span = value_span(&prog_input, value);
+        if key == "partitions" {
+            let json_parts: Vec<&RawValue> = match serde_json::from_str(value.get()) {
+                Ok(json_parts) => json_parts,
+                Err(_) => {
+                    return Err(ParseError::Json(
+                        span,
+                        String::from("partitions is not an array"),
+                    ))
+                }
+            };
+            for json_part in json_parts {
+                let (part, name_span) = parse_json_partition(&prog_input, json_part)?;
+                spans.insert(&part.name, name_span);
+                partitions.push(part);
+            }
+            continue;
+        }
+
+        parse_json_header_field(&mut header, &key, to_value(value), span.clone())?;
+        if key == "device" {
+            spans.insert(&header.device, span);
+        }
+    }
+
+    if header.device.is_empty() {
+        return Err(ParseError::MissingDevice(Span::end_of(&prog_input)));
+    }
+
+    Disk::from_parsed(header, partitions, spans)
+}
+
+/// Parses one field of `partitiontable` other than `partitions` into `header`.
+/// Fields with unknown keys are kept with their JSON value.
+fn parse_json_header_field(
+    header: &mut DiskHeader,
+    key: &str,
+    value: Value,
+    span: Span,
+) -> Result<(), ParseError> {
+    match key {
+        "label" => {
+            let label = json_scalar(&value)
+                .parse()
+                .map_err(|err: ParseError| err.at(span))?;
+            header.label = Some(label);
+        }
+        "id" => header.label_id = Some(json_scalar(&value)),
+        "device" => {
+            let device = json_scalar(&value);
+            header::check_device(&device, &span)?;
+            header.device = device;
+        }
+        "unit" => header.unit = Some(json_scalar(&value)),
+        "firstlba" => header.first_lba = Some(json_number(&value, span)?),
+        "lastlba" => header.last_lba = Some(json_number(&value, span)?),
+        "table-length" => header.table_length = Some(json_number(&value, span)?),
+        "grain" => header.grain = Some(json_number(&value, span)?),
+        "sectorsize" => {
+            let sector_size = json_number(&value, span.clone())?;
+            header.sector_size = Some(header::check_sector_size(sector_size, &span)?);
+        }
+        _ => header.extras.push((String::from(key), value)),
+    }
+
+    Ok(())
+}
+
+/// Parses one element of `partitions`, and returns it with where its name is.
+fn parse_json_partition(
+    input: &str,
+    json_part: &RawValue,
+) -> Result<(Partition, Span), ParseError> {
+    let mut part = Partition::default();
+    let mut name_span = value_span(input, json_part);
+    for (key, raw) in object_fields(input, json_part, "partition")? {
+        let span = value_span(input, raw);
+        let value = to_value(raw);
+        match key.as_str() {
+            "node" => {
+                part.name = json_scalar(&value);
+                name_span = span;
+            }
+            "start" => part.start_block = json_number(&value, span)?,
+            "size" => part.size = Some(json_number(&value, span)?),
+            "type" => part.part_type = Some(PartitionType::parse(&json_scalar(&value))),
+            "uuid" => part.uuid = Some(json_scalar(&value)),
+            "name" => part.label = Some(json_scalar(&value)),
+            "attrs" => {
+                part.attrs = json_scalar(&value)
+                    .split_whitespace()
+                    .map(String::from)
+                    .collect();
+            }
+            "bootable" => match value {
+                Value::Bool(bootable) => part.bootable = bootable,
+                _ => {
+                    return Err(ParseError::Json(
+                        span,
+                        String::from("bootable is not a boolean"),
+                    ))
+                }
+            },
+            _ => part.extras.push(match value {
+                Value::String(s) => format!("{}={}", key, token::quote(&s)),
+                Value::Bool(true) => key.clone(),
+                _ => format!("{}={}", key, value),
+            }),
+        }
+    }
+
+    part.designation = partition::designation_from_name(&part.name)
+        .map_err(|err| err.at(name_span.clone()))?;
+
+    Ok((part, name_span))
+}
+
+/// Returns the fields of the JSON object `value`, in input order, or an error naming it
+/// `what` if it is not an object.
+fn object_fields<'a>(
+    input: &str,
+    value: &'a RawValue,
+    what: &str,
+) -> Result<Vec<(String, &'a RawValue)>, ParseError> {
+    let not_object = || {
+        ParseError::Json(
+            value_span(input, value),
+            format!("{} is not an object", what),
+        )
+    };
+    // The raw fields locate the values, the parsed object keeps their order
+    let ordered: Map<String, Value> =
+        serde_json::from_str(value.get()).map_err(|_| not_object())?;
+    let mut fields: HashMap<String, &RawValue> =
+        serde_json::from_str(value.get()).map_err(|_| not_object())?;
+
+    Ok(ordered
+        .keys()
+        .filter_map(|key| fields.remove_entry(key))
+        .collect())
+}
+
+/// Points at `value`, a part of `input`, leaving out the quotes of a string.
+fn value_span(input: &str, value: &RawValue) -> Span {
+    let raw = value.get();
+    let start = match (raw.as_ptr() as usize).checked_sub(input.as_ptr() as usize) {
+        Some(start) if start + raw.len() <= input.len() => start,
+        _ => return Span::end_of(input),
+    };
+
+    let quoted = raw.len() >= 2 && raw.starts_with('"');
+    match quoted {
+        true => Span::in_input(input, start + 1..start + raw.len() - 1),
+        false => Span::in_input(input, start..start + raw.len()),
+    }
+}
+
+fn to_value(raw: &RawValue) -> Value {
+    serde_json::from_str(raw.get()).unwrap_or_default()
+}
+
+/// Serializes Disk into JSON in the `sfdisk -J` layout, which `sfdisk` can read back.
+pub fn to_sfdisk_json(disk: &Disk) -> Result<String> {
+    let header = &disk.header;
+    let mut table = Map::new();
+
+    if let Some(label) = header.label {
+        table.insert(String::from("label"), json!(label.to_string()));
+    }
+    if let Some(label_id) = &header.label_id {
+        table.insert(String::from("id"), json!(label_id));
+    }
+    table.insert(String::from("device"), json!(header.device));
+    if let Some(unit) = &header.unit {
+        table.insert(String::from("unit"), json!(unit));
+    }
+
+    let numbers = [
+        ("firstlba", header.first_lba),
+        ("lastlba", header.last_lba),
+        ("table-length", header.table_length),
+        ("grain", header.grain),
+        ("sectorsize", header.sector_size),
+    ];
+    for (key, value) in numbers {
+        if let Some(value) = value {
+            table.insert(String::from(key), json!(value));
+        }
+    }
+    for (key, value) in &header.extras {
+        table.insert(key.clone(), value.clone());
+    }
+
+    let mut json_parts: Vec<Value> = Vec::new();
+    for part in &disk.partitions {
+        json_parts.push(partition_to_json(part)?);
+    }
+    table.insert(String::from("partitions"), Value::Array(json_parts));
+
+    let root = json!({ "partitiontable": table });
+    match serde_json::to_string_pretty(&root) {
+        Ok(text) => Ok(text),
+        Err(err) => Err(Error::from(err))
+            .with_context(|| String::from("failed to serialize disk")),
+    }
+}
+
+fn partition_to_json(part: &Partition) -> Result<Value> {
+    let mut obj = Map::new();
+
+    obj.insert(String::from("node"), json!(part.name));
+    obj.insert(String::from("start"), json!(part.start_block));
+    if let Some(size) = part.size {
+        obj.insert(String::from("size"), json!(size));
+    }
+    if let Some(part_type) = &part.part_type {
+        obj.insert(String::from("type"), json!(part_type.to_string()));
+    }
+    if let Some(uuid) = &part.uuid {
+        obj.insert(String::from("uuid"), json!(uuid));
+    }
+    if let Some(label) = &part.label {
+        obj.insert(String::from("name"), json!(label));
+    }
+    if !part.attrs.is_empty() {
+        obj.insert(String::from("attrs"), json!(part.attrs.join(" ")));
+    }
+    if part.bootable {
+        obj.insert(String::from("bootable"), json!(true));
+    }
+
+    for extra in &part.extras {
+        let fields = match token::tokenize(extra) {
+            Ok(fields) => fields,
+            Err(err) => {
+                return Err(err).with_context(|| {
+                    format!("failed to tokenize extra field {}", extra)
+                });
+            }
+        };
+        for field in fields {
+            let value = match field.value {
+                Some(value) => json!(value),
+                None => json!(true),
+            };
+            obj.insert(field.key, value);
+        }
+    }
+
+    Ok(Value::Object(obj))
+}
+
+fn json_scalar(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        _ => value.to_string(),
+    }
+}
+
+fn json_number(value: &Value, span: Span) -> Result<u64, ParseError> {
+    match str::parse::<u64>(&json_scalar(value)) {
+        Ok(num) => Ok(num),
+        Err(_) => Err(ParseError::InvalidNumber(span)),
+    }
+}
+
+#[cfg(test)]
+mod json_test {
+    use super::{is_sfdisk_json, parse_sfdisk_json_disk, to_sfdisk_json};
+    use crate::error::{ParseError, Span};
+    use crate::disk::parse_sfdisk_full_disk;
+
+    use serde_json::json;
+
+    use std::fs;
+
+    #[test]
+    fn test_parse_json() {
+        let json_input = fs::read_to_string("./assets/sfdisk_output.json")
+            .expect("failed to read JSON test file");
+        let text_input = fs::read_to_string("./assets/sfdisk_output.txt")
+            .expect("failed to read text test file");
+
+        assert!(is_sfdisk_json(&json_input));
+        assert!(!is_sfdisk_json(&text_input));
+
+        let json_disk = parse_sfdisk_json_disk(json_input).unwrap();
+        let text_disk = parse_sfdisk_full_disk(text_input).unwrap();
+        assert_eq!(json_disk, text_disk);
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let input = r#"{
+  "partitiontable": {
+    "label": "dos",
+    "id": "0x1234abcd",
+    "device": "/dev/vda",
+    "unit": "sectors",
+    "sectorsize": 512,
+    "myKey_2": 5,
+    "partitions": [
+      {
+        "node": "/dev/vda1",
+        "start": 2048,
+        "size": 1048576,
+        "type": "83",
+        "bootable": true,
+        "foo": "bar, baz"
+      }
+    ]
+  }
+}"#;
+
+        let disk = parse_sfdisk_json_disk(String::from(input)).unwrap();
+        assert!(disk.partitions[0].bootable);
+        assert_eq!(disk.partitions[0].extras, vec![r#"foo="bar, baz""#]);
+        // Unknown header keys keep their JSON type
+        assert_eq!(
+            disk.header.extras,
+            vec![(String::from("myKey_2"), json!(5))]
+        );
+
+        let output = to_sfdisk_json(&disk).unwrap();
+        assert_eq!(output, input);
+        assert_eq!(parse_sfdisk_json_disk(output).unwrap(), disk);
+
+        assert!(parse_sfdisk_json_disk(String::from("{}")).is_err());
+        assert!(parse_sfdisk_json_disk(String::from("{ not json")).is_err());
+
+        let bad_size = input.replace("1048576", "\"lots\"");
+        let err = parse_sfdisk_json_disk(bad_size).unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::InvalidNumber(Span::new(
+                13,
+                17..21,
+                r#"        "size": "lots","#
+            ))
+        );
+
+        let bad_bootable = input.replace("\"bootable\": true", "\"bootable\": \"yes\"");
+        let err = parse_sfdisk_json_disk(bad_bootable).unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::Json(
+                Span::new(15, 21..24, r#"        "bootable": "yes","#),
+                String::from("bootable is not a boolean")
+            )
+        );
+
+        let zero_sectors = input.replace("\"sectorsize\": 512", "\"sectorsize\": 0");
+        let err = parse_sfdisk_json_disk(zero_sectors).unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::InvalidSectorSize(Span::new(
+                7,
+                18..19,
+                r#"    "sectorsize": 0,"#
+            ))
+        );
+
+        let no_number = input.replace("\"/dev/vda1\"", "\"/dev/vda\"");
+        let err = parse_sfdisk_json_disk(no_number).unwrap_err();
+        assert_eq!(
+            err.span(),
+            &Span::new(11, 17..25, r#"        "node": "/dev/vda","#)
+        );
+    }
+
+    #[test]
+    fn test_json_designation() {
+        // Names containing e.g. `vd[a-z]` are numbered like in text dumps
+        let json_input = r#"{
+  "partitiontable": {
+    "device": "/dev/mapper/vdisk",
+    "partitions": [
+      {"node": "/dev/mapper/vdisk2", "start": 2048}
+    ]
+  }
+}"#;
+        let text_input = "device: /dev/mapper/vdisk\n/dev/mapper/vdisk2 : start= 2048\n";
+
+        let json_disk = parse_sfdisk_json_disk(String::from(json_input)).unwrap();
+        assert_eq!(json_disk.partitions[0].designation, 2);
+        assert_eq!(
+            json_disk,
+            parse_sfdisk_full_disk(String::from(text_input)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_json_spans() {
+        let input = fs::read_to_string("./assets/sfdisk_output.json")
+            .expect("failed to read JSON test file");
+        let disk = parse_sfdisk_json_disk(input.clone()).unwrap();
+
+        // Each name is located at its own value
+        for name in [disk.name.as_str(), disk.partitions[1].name.as_str()] {
+            let span = disk.spans().get(name).unwrap();
+            assert_eq!(span.snippet(), name);
+            assert_eq!(input.lines().nth(span.line - 1), Some(span.text.as_str()));
+        }
+    }
+}
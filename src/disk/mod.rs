@@ -1,178 +1,520 @@
-use super::partition::{Partition, parse};
-use crate::linux::block;
-use crate::error::RegexCapturesError;
+pub(crate) mod align;
+pub(crate) mod dos;
+pub(crate) mod free;
+pub(crate) mod header;
+pub(crate) mod json;
+pub(crate) mod numbering;
+pub(crate) mod pin;
+pub(crate) mod sort;
+pub(crate) mod sysfs;
+pub(crate) mod validate;
 
-use lazy_static::lazy_static;
-use regex::Regex;
-use anyhow::{Error, Result, Context};
+use super::partition::{Partition, Renaming, parse};
+use crate::linux::{block, naming};
+use crate::error::{LayoutError, NameSpans, ParseError, Span};
+use header::{DiskHeader, Label};
+use numbering::Numbering;
+use pin::Pin;
+use sort::SortKey;
 
-const SFDISK_DEVICE_NAME_PATTERN: &str = r"(?:device:\s+)(?P<device_name>(?:/dev/).*)";
+/// Parses the `sfdisk -d` text output into Disk.
+pub fn parse_sfdisk_full_disk(prog_input: String) -> Result<Disk, ParseError> {
+    let mut header = DiskHeader::default();
+    let mut partitions: Vec<Partition> = Vec::new();
+    let mut spans = NameSpans::default();
 
-lazy_static! {
-    static ref SFDISK_DEVICE_NAME_REGEX: Regex =
-        Regex::new(SFDISK_DEVICE_NAME_PATTERN).unwrap();
-}
+    for (c, input_line) in prog_input.lines().enumerate() {
+        // Skip blank lines and comments, e.g. commented-out partition lines
+        let trimmed = input_line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
 
-pub fn is_sfdisk_device_name_line(s: &str) -> bool {
-    SFDISK_DEVICE_NAME_REGEX.is_match(s)
-}
+        // Parse partition line (will continue)
+        if parse::is_sfdisk_partition_line(input_line) {
+            let (part, mut span) = parse::parse_sfdisk_partition_line(input_line)
+                .map_err(|err| err.on_line(c + 1))?;
+            span.line = c + 1;
+            spans.insert(&part.name, span);
+            partitions.push(part);
+            continue;
+        }
 
-pub fn parse_sfdisk_device_name_line(s: &str) -> Result<String> {
-    let caps = SFDISK_DEVICE_NAME_REGEX.captures(s);
-    if caps.is_none() {
-        return Err(Error::from(RegexCapturesError)).with_context(|| {
-            format!("failed to parse device name from 'device' line: {}", s)
-        });
+        // Parse header lines, e.g. `device: /dev/sda`
+        let indent = input_line.len() - input_line.trim_start().len();
+        if !header::is_sfdisk_header_line(trimmed) {
+            let span = Span::new(c + 1, indent..indent + trimmed.len(), input_line);
+            return Err(ParseError::UnrecognizedLine(span));
+        }
+        let (key, span) = header
+            .parse_key_value(trimmed)
+            .map_err(|err| err.within(indent, input_line).on_line(c + 1))?;
+        if key == "device" {
+            let columns = span.columns.start + indent..span.columns.end + indent;
+            spans.insert(&header.device, Span::new(c + 1, columns, input_line));
+        }
     }
-    let caps = caps.unwrap();
 
-    let device_name = caps.name("device_name");
-    if device_name.is_none() {
-        return Err(Error::from(RegexCapturesError)).with_context(|| {
-            format!("failed to parse device name from 'device' line: {}", s)
-        });
+    if header.device.is_empty() {
+        return Err(ParseError::MissingDevice(Span::end_of(&prog_input)));
     }
 
-    Ok(String::from(device_name.unwrap().as_str()))
+    Disk::from_parsed(header, partitions, spans)
 }
 
-/// Parses the `sfdisk -d` text output into Disk.
-pub fn parse_sfdisk_full_disk(prog_input: String) -> Result<Disk> {
-    let mut device_name: Option<String> = None;
-    let mut header_lines: Vec<String> = Vec::new();
-    let mut partitions: Vec<Partition> = Vec::new();
+/// Parses concatenated `sfdisk -d` outputs, e.g. of every disk of a host, into one Disk
+/// per dump. A dump ends where a header line follows its partition lines, or where a
+/// header key repeats, e.g. a second `label:` or `device:` line.
+pub fn parse_sfdisk_disks(prog_input: String) -> Result<Vec<Disk>, ParseError> {
+    let mut dumps: Vec<(usize, String)> = Vec::new();
+    let mut keys: Vec<String> = Vec::new();
+    let mut has_partitions = false;
 
-    for (c, input_line) in prog_input.lines().collect::<Vec<&str>>().iter().enumerate() {
-        // Parse partition line (will continue)
-        if parse::is_sfdisk_partition_line(input_line) {
-            let part = match parse::parse_sfdisk_partition_line(input_line) {
-                Ok(valid_partition) => valid_partition,
-                Err(err) => {
-                    return Err(err).with_context(|| {
-                        format!("error parsing partition on line {}", c + 1)
-                    });
-                }
-            };
-            partitions.push(part);
-            continue;
+    for (c, input_line) in prog_input.lines().enumerate() {
+        let trimmed = input_line.trim();
+        let key = match trimmed.split_once(':') {
+            Some((key, _))
+                if !parse::is_sfdisk_partition_line(input_line)
+                    && header::is_sfdisk_header_line(trimmed) =>
+            {
+                Some(String::from(key))
+            }
+            _ => None,
+        };
+
+        let starts_dump = match &key {
+            Some(key) => has_partitions || keys.contains(key),
+            None => false,
+        };
+        if dumps.is_empty() || starts_dump {
+            dumps.push((c, String::new()));
+            keys.clear();
+            has_partitions = false;
         }
 
-        // Parse device name (part of so-called 'header lines'), won't continue
-        if is_sfdisk_device_name_line(input_line) {
-            match parse_sfdisk_device_name_line(input_line) {
-                Ok(text) => {
-                    device_name = Some(text);
-                }
-                Err(err) => {
-                    return Err(err).with_context(|| {
-                        format!("error parsing device name on line {}", c + 1)
-                    });
-                }
-            }
+        match key {
+            Some(key) => keys.push(key),
+            None => has_partitions |= parse::is_sfdisk_partition_line(input_line),
         }
-        header_lines.push(String::from(*input_line));
+        let (_, dump) = dumps.last_mut().unwrap();
+        dump.push_str(input_line);
+        dump.push('\n');
     }
 
-    if device_name.is_none() {
-        return Err(Error::from(RegexCapturesError))
-            .with_context(|| String::from("missing device_name"));
-    }
-    if header_lines.is_empty() {
-        return Err(Error::from(RegexCapturesError))
-            .with_context(|| String::from("missing header lines"));
+    if dumps.is_empty() {
+        return Err(ParseError::MissingDevice(Span::end_of(&prog_input)));
     }
 
-    let device_name = device_name.unwrap();
-    let this_disk = match Disk::new(&device_name, header_lines, partitions) {
-        Ok(d) => d,
-        Err(err) => {
-            return Err(err).with_context(|| String::from("error creating new disk"));
-        }
-    };
+    dumps
+        .into_iter()
+        .map(|(offset, dump)| match parse_sfdisk_full_disk(dump) {
+            Ok(mut this_disk) => {
+                this_disk.spans.offset_lines(offset);
+                Ok(this_disk)
+            }
+            Err(err) => {
+                let line = err.span().line + offset;
+                Err(err.on_line(line))
+            }
+        })
+        .collect()
+}
 
-    Ok(this_disk)
+/// How `Disk::rearrange_with` orders and numbers the partitions.
+#[derive(Default, Debug, PartialEq, Eq, Clone)]
+#[non_exhaustive]
+pub struct Arrangement {
+    /// Order of the partitions that are not pinned
+    pub sort_by: SortKey,
+    /// Partitions that keep a fixed designation, the others are numbered around them
+    pub pins: Vec<Pin>,
+    /// Designations given to the partitions that are not pinned
+    pub numbering: Numbering,
+}
+
+impl Arrangement {
+    /// Creates an arrangement, e.g. for callers outside the crate, which cannot use a
+    /// struct literal.
+    pub fn new(sort_by: SortKey, pins: Vec<Pin>, numbering: Numbering) -> Self {
+        Arrangement {
+            sort_by,
+            pins,
+            numbering,
+        }
+    }
 }
 
-#[derive(Default, Debug, PartialEq)]
+/// A disk and its partition table, as parsed from a dump or read from a disk image.
+/// Its name, the `device:` of the header, decides how its partitions are named.
+#[derive(Default, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "StoredDisk", into = "StoredDisk"))]
 pub struct Disk {
-    pub name: String,
-    pub linux_block_device: block::LinuxBlockDevice,
-    pub header_lines: Vec<String>,
-    pub partitions: Vec<Partition>,
+    pub(crate) name: String,
+    /// Kind of the disk, if its name is one of the common ones. Partition names follow
+    /// from `name` alone, see `linux::naming`
+    pub(crate) linux_block_device: Option<block::LinuxBlockDevice>,
+    pub(crate) header: DiskHeader,
+    pub(crate) partitions: Vec<Partition>,
+    /// Where the names were parsed from, see `spans`
+    pub(crate) spans: NameSpans,
+}
+
+/// Disks are equal when their header and partitions are. The name and kind follow from
+/// the header, and where the names were parsed from is not part of the disk.
+impl PartialEq for Disk {
+    fn eq(&self, other: &Self) -> bool {
+        self.header == other.header && self.partitions == other.partitions
+    }
+}
+
+/// The serialized form of a Disk. Its name and kind follow from `header.device`, so a
+/// stored disk is loaded through `Disk::new`, like a parsed one.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct StoredDisk {
+    header: DiskHeader,
+    partitions: Vec<Partition>,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<StoredDisk> for Disk {
+    type Error = LayoutError;
+
+    fn try_from(stored: StoredDisk) -> Result<Self, LayoutError> {
+        Disk::new(stored.header, stored.partitions)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<Disk> for StoredDisk {
+    fn from(disk: Disk) -> Self {
+        StoredDisk {
+            header: disk.header,
+            partitions: disk.partitions,
+        }
+    }
 }
 
 impl Disk {
+    /// Creates the disk `header.device` with `partitions`, which keep their names and
+    /// designations until it is rearranged. Fails if the device cannot name a disk, e.g.
+    /// `/dev/sda1`, see `naming::is_disk_name`.
     pub fn new(
-        disk_name: &str,
-        header_lines: Vec<String>,
+        header: DiskHeader,
         partitions: Vec<Partition>,
-    ) -> Result<Self> {
-        if let Some(correct_linux_device) = block::linux_blk_name(disk_name) {
-            return Ok(Disk {
-                name: String::from(disk_name),
-                linux_block_device: correct_linux_device,
-                header_lines,
-                partitions,
-            });
+    ) -> Result<Self, LayoutError> {
+        if !naming::is_disk_name(&header.device) {
+            return Err(LayoutError::UnsupportedDevice(header.device));
         }
 
-        Err(Error::from(RegexCapturesError)).with_context(|| format!(
-            "disk name does match known Linux block device name (e.g. sdX, vdX, or nvmeXnY)"
-        ))
+        Ok(Disk {
+            name: header.device.clone(),
+            linux_block_device: block::linux_blk_name(&header.device),
+            header,
+            partitions,
+            spans: NameSpans::default(),
+        })
     }
 
-    /// Sorts and reassigns partition name and designation. It assumes first partition starts at 1.
-    pub fn rearrange(&mut self) -> Result<(), String> {
-        // Sort partition by start_block
-        self.partitions
-            .sort_by(|a, b| a.start_block.cmp(&b.start_block));
+    /// Returns the device path of the disk, e.g. `/dev/sda`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
 
-        // Redesignate all partitions based on sorted indices
-        for (i, part) in self.partitions.iter_mut().enumerate() {
-            if let Some(re) = block::BLK_REGEX.get(&self.linux_block_device) {
-                // Check if part.name is a valid Regex.
-                if re.captures(&part.name).is_none() {
-                    return Err(String::from(format!(
-                        "failed to get partition number for {}",
-                        &part.name
-                    )));
-                }
-
-                // Redesignate (update) partition fields to reflect the new sorted name.
-                if let Err(err) = part.redesignate(self.linux_block_device, i + 1) {
-                    return Err(format!(
-                        "error redesignating partition {}: {}",
-                        part.name, err
-                    ));
-                }
-            } else {
-                return Err(String::from(
-                    "missing regex for parsing partition prefix and number",
-                ));
+    /// Returns the kind of the disk, if its name is one of the common ones, e.g. `SCSI`
+    /// for `/dev/sda`.
+    pub fn linux_block_device(&self) -> Option<block::LinuxBlockDevice> {
+        self.linux_block_device
+    }
+
+    /// Returns the header lines of the dump.
+    pub fn header(&self) -> &DiskHeader {
+        &self.header
+    }
+
+    /// Returns the partitions, in the order of the dump until the disk is rearranged.
+    pub fn partitions(&self) -> &[Partition] {
+        &self.partitions
+    }
+
+    /// Gives the partitions to edit in place. Their names are kept, so rearrange the
+    /// disk afterwards if start blocks moved.
+    pub fn partitions_mut(&mut self) -> &mut [Partition] {
+        &mut self.partitions
+    }
+
+    /// Returns where the parser found the names of the disk and its partitions, empty
+    /// for a disk that was not parsed from text.
+    pub fn spans(&self) -> &NameSpans {
+        &self.spans
+    }
+
+    /// Builds the Disk of a parsed dump, which keeps `spans`. An error of `Disk::new`
+    /// points at the name it is about.
+    pub(crate) fn from_parsed(
+        header: DiskHeader,
+        partitions: Vec<Partition>,
+        spans: NameSpans,
+    ) -> Result<Self, ParseError> {
+        match Disk::new(header, partitions) {
+            Ok(mut this_disk) => {
+                this_disk.spans = spans;
+                Ok(this_disk)
+            }
+            Err(err) => {
+                let span = err.span(&spans).unwrap_or_default();
+                Err(match err {
+                    LayoutError::UnsupportedDevice(_) => {
+                        ParseError::UnsupportedDevice(span)
+                    }
+                    err => ParseError::Layout(span, err),
+                })
             }
         }
+    }
+
+    /// Sorts partitions by start block and reassigns their names and designations,
+    /// see `rearrange_by`.
+    pub fn rearrange(&mut self) -> Result<Vec<Renaming>, LayoutError> {
+        self.rearrange_by(&SortKey::default())
+    }
 
-        Ok(())
+    /// Sorts partitions by `key` and reassigns their names and designations,
+    /// see `rearrange_with`.
+    pub fn rearrange_by(&mut self, key: &SortKey) -> Result<Vec<Renaming>, LayoutError> {
+        self.rearrange_with(&Arrangement {
+            sort_by: key.clone(),
+            ..Default::default()
+        })
+    }
+
+    /// Sorts and reassigns partition name and designation. Partitions are numbered from 1
+    /// unless `arrangement.numbering` starts elsewhere, leaves gaps or keeps the numbers
+    /// already in use. On DOS disks, this applies to the primaries numbered 1-4, while
+    /// logicals are always numbered from 5, see `dos::arrange`.
+    /// Pinned partitions keep their pinned designation, and the others are sorted into
+    /// the remaining ones, see `numbering::number`. On GPT, no partition is numbered past
+    /// the table length, see `DiskHeader::last_designation`.
+    /// Returns how each partition was renamed, in the new partition order.
+    pub fn rearrange_with(
+        &mut self,
+        arrangement: &Arrangement,
+    ) -> Result<Vec<Renaming>, LayoutError> {
+        let key = &arrangement.sort_by;
+        let pinned = pin::resolve(&self.partitions, &arrangement.pins)?;
+
+        let designations: Vec<usize> = match self.header.label {
+            Some(Label::Dos) => {
+                let primaries = dos::arrange(&mut self.partitions, key)?
+                    .iter()
+                    .filter(|designation| **designation <= dos::MAX_PRIMARY)
+                    .count();
+                let (primaries, logicals) = self.partitions.split_at(primaries);
+                // Logicals are numbered by their order in the chain, so without holes
+                let last_logical = dos::FIRST_LOGICAL + logicals.len() - 1;
+
+                let mut designations = numbering::number(
+                    primaries,
+                    &pinned,
+                    &arrangement.numbering,
+                    1,
+                    Some(dos::MAX_PRIMARY),
+                )?;
+                designations.extend(numbering::number(
+                    logicals,
+                    &pinned,
+                    &Numbering::default(),
+                    dos::FIRST_LOGICAL,
+                    Some(last_logical),
+                )?);
+                designations
+            }
+            _ => {
+                self.partitions.sort_by(|a, b| key.compare(a, b));
+                numbering::number(
+                    &self.partitions,
+                    &pinned,
+                    &arrangement.numbering,
+                    1,
+                    self.header.last_designation(),
+                )?
+            }
+        };
+
+        // Pins can move partitions ahead of the ones sorted before them
+        let mut numbered: Vec<(usize, Partition)> = designations
+            .into_iter()
+            .zip(self.partitions.drain(..))
+            .collect();
+        numbered.sort_by_key(|(designation, _)| *designation);
+        let (designations, partitions): (Vec<usize>, Vec<Partition>) =
+            numbered.into_iter().unzip();
+        self.partitions = partitions;
+
+        // Redesignate all partitions based on sorted indices
+        let mut renamings: Vec<Renaming> = Vec::with_capacity(self.partitions.len());
+        for (part, designation) in self.partitions.iter_mut().zip(designations) {
+            // Redesignate (update) partition fields to reflect the new sorted name.
+            renamings.push(part.redesignate(&self.name, designation)?);
+        }
+
+        Ok(renamings)
+    }
+
+    /// Reports whether the partitions are already in start block order, see `is_sorted_by`.
+    pub fn is_sorted(&self) -> Result<bool, LayoutError> {
+        self.is_sorted_by(&SortKey::default())
+    }
+
+    /// Reports whether the partitions are already sorted by `key`, see `is_sorted_with`.
+    pub fn is_sorted_by(&self, key: &SortKey) -> Result<bool, LayoutError> {
+        self.is_sorted_with(&Arrangement {
+            sort_by: key.clone(),
+            ..Default::default()
+        })
+    }
+
+    /// Reports whether the partitions are already in the order and numbering that
+    /// `rearrange_with(arrangement)` would produce. The disk itself is left untouched.
+    pub fn is_sorted_with(&self, arrangement: &Arrangement) -> Result<bool, LayoutError> {
+        let mut sorted = self.clone();
+        sorted.rearrange_with(arrangement)?;
+
+        Ok(&sorted == self)
     }
 }
 
 #[cfg(test)]
 mod disk_test {
-    use super::{block, Disk, SFDISK_DEVICE_NAME_REGEX};
+    use crate::error::{ParseError, Span};
+    use super::header::{DiskHeader, Label};
+    use super::numbering::Numbering;
+    use super::sort::SortKey;
+    use super::{block, parse_sfdisk_disks, parse_sfdisk_full_disk, Arrangement, Disk};
+    use crate::error::LayoutError;
     use crate::partition::Partition;
 
     #[test]
-    fn test_device_name() {
-        let names = vec![
-            "device: /dev/sda",
-            "device: /dev/vdz",
-            "device: /dev/mmcblk2",
-            "device: /dev/nvme17n1",
+    fn test_parse_header() {
+        let input = std::fs::read_to_string("./assets/sfdisk_output.txt")
+            .expect("failed to read test text file");
+        let sda = parse_sfdisk_full_disk(input).unwrap();
+
+        assert_eq!(sda.name, "/dev/sda");
+        assert_eq!(sda.partitions.len(), 4);
+        assert_eq!(
+            sda.header,
+            DiskHeader {
+                label: Some(Label::Gpt),
+                label_id: Some(String::from("12345678-2345-6969-3264-A55555555555")),
+                device: String::from("/dev/sda"),
+                unit: Some(String::from("sectors")),
+                first_lba: Some(2048),
+                last_lba: Some(976773134),
+                sector_size: Some(512),
+                ..Default::default()
+            }
+        );
+
+        let errs = [
+            (
+                "label: gpt\n",
+                ParseError::MissingDevice(Span::new(2, 0..0, "")),
+            ),
+            (
+                "device: /dev/sda\n  garbage\n",
+                ParseError::UnrecognizedLine(Span::new(2, 2..9, "  garbage")),
+            ),
+            (
+                "device: /dev/sda\nlabel: sun\n",
+                ParseError::UnknownLabel(Span::new(2, 7..10, "label: sun")),
+            ),
+            (
+                "device: /dev/sda/\n",
+                ParseError::UnsupportedDevice(Span::new(1, 8..17, "device: /dev/sda/")),
+            ),
+            (
+                "device: /dev/sda1\n",
+                ParseError::UnsupportedDevice(Span::new(1, 8..17, "device: /dev/sda1")),
+            ),
+            (
+                "device: /dev/loop\n/dev/loop0 : start= 2048\n",
+                ParseError::MissingPartitionNumber(Span::new(
+                    2,
+                    0..10,
+                    "/dev/loop0 : start= 2048",
+                )),
+            ),
+            (
+                "device: disk.img\ndisk.img0 : start= 2048\n",
+                ParseError::ZeroPartitionNumber(Span::new(
+                    2,
+                    8..9,
+                    "disk.img0 : start= 2048",
+                )),
+            ),
         ];
+        for (input, expected) in errs {
+            assert_eq!(parse_sfdisk_full_disk(String::from(input)), Err(expected));
+        }
+
+        // Commented-out partition lines are not partitions
+        let input = "device: /dev/sda\n/dev/sda2 : start= 4096\n\
+                     # /dev/sda9 : start=2048, size=1024, type=83\n\
+                     \t# /dev/sda8 : start= 1024\n";
+        let sda = parse_sfdisk_full_disk(String::from(input)).unwrap();
+        let names: Vec<&str> = sda.partitions.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["/dev/sda2"]);
+        let disks = parse_sfdisk_disks(String::from(input)).unwrap();
+        assert_eq!(disks, vec![sda]);
 
-        for name in names {
-            assert!(SFDISK_DEVICE_NAME_REGEX.is_match(name));
+        // Whole disks numbered by their drivers
+        for device in ["/dev/loop0", "/dev/md127", "/dev/nbd0"] {
+            let input = format!("device: {}\n{}p1 : start= 2048\n", device, device);
+            let disk = parse_sfdisk_full_disk(input).unwrap();
+            assert_eq!(disk.partitions[0].designation, 1);
+            assert!(disk.validate().is_empty());
         }
+
+        // Any disk name is supported, not only the classified ones
+        let foo = parse_sfdisk_full_disk(String::from("device: /dev/foo\n")).unwrap();
+        assert_eq!(foo.name, "/dev/foo");
+        assert_eq!(foo.linux_block_device, None);
+    }
+
+    #[test]
+    fn test_parse_disks() {
+        let input = std::fs::read_to_string("./assets/sfdisk_output_multi_ugly.txt")
+            .expect("failed to read multi-disk test text file");
+        let disks = parse_sfdisk_disks(input).unwrap();
+
+        let names: Vec<&str> = disks.iter().map(|disk| disk.name.as_str()).collect();
+        assert_eq!(names, vec!["/dev/sda", "/dev/vda"]);
+        assert_eq!(disks[0].partitions.len(), 4);
+        assert_eq!(disks[1].header.label, Some(Label::Dos));
+        assert_eq!(disks[1].partitions.len(), 5);
+
+        // A single dump parses the same as with parse_sfdisk_full_disk
+        let input = std::fs::read_to_string("./assets/sfdisk_output.txt")
+            .expect("failed to read test text file");
+        assert_eq!(
+            parse_sfdisk_disks(input.clone()),
+            parse_sfdisk_full_disk(input).map(|disk| vec![disk])
+        );
+
+        // Error lines are counted from the start of the whole input
+        let input = "device: /dev/sda\n/dev/sda1 : start= 2048\nlabel: sun\n";
+        assert_eq!(
+            parse_sfdisk_disks(String::from(input)),
+            Err(ParseError::UnknownLabel(Span::new(3, 7..10, "label: sun")))
+        );
+        assert_eq!(
+            parse_sfdisk_disks(String::from(
+                "label: gpt\nlabel: dos\ndevice: /dev/sda\n"
+            )),
+            Err(ParseError::MissingDevice(Span::new(2, 0..0, "")))
+        );
     }
 
     #[test]
@@ -186,19 +528,36 @@ mod disk_test {
         let p2069 =
             Partition::new_from_start_block(4, 2069, block::LinuxBlockDevice::SCSI);
 
-        let mut expecteds =
-            vec![p1969.clone(), p2022.clone(), p2048.clone(), p2069.clone()];
+        let mut expecteds = [p1969.clone(), p2022.clone(), p2048.clone(), p2069.clone()];
 
         let mut sda = Disk {
             name: String::from("/dev/sda"),
-            linux_block_device: super::block::LinuxBlockDevice::SCSI,
-            header_lines: Vec::new(),
+            linux_block_device: Some(super::block::LinuxBlockDevice::SCSI),
+            header: DiskHeader::default(),
             partitions: vec![p2048, p2069, p2022, p1969],
+            ..Default::default()
         };
 
-        if let Err(err) = sda.rearrange() {
-            panic!("rearrange failed: {}", err);
-        }
+        assert_eq!(sda.is_sorted(), Ok(false));
+
+        let renamings = match sda.rearrange() {
+            Ok(renamings) => renamings,
+            Err(err) => panic!("rearrange failed: {}", err),
+        };
+
+        let old_new: Vec<(&str, &str)> = renamings
+            .iter()
+            .map(|r| (r.old_name.as_str(), r.new_name.as_str()))
+            .collect();
+        assert_eq!(
+            old_new,
+            vec![
+                ("/dev/sda3", "/dev/sda1"),
+                ("/dev/sda2", "/dev/sda2"),
+                ("/dev/sda1", "/dev/sda3"),
+                ("/dev/sda4", "/dev/sda4"),
+            ]
+        );
 
         for (i, sorted) in sda.partitions.iter().enumerate() {
             let expected = expecteds.get_mut(i).unwrap();
@@ -208,5 +567,244 @@ mod disk_test {
 
             assert_eq!(sorted, expected);
         }
+
+        assert_eq!(sda.is_sorted(), Ok(true));
+    }
+
+    #[test]
+    fn test_rearrange_by() {
+        let input = std::fs::read_to_string("./assets/sfdisk_output.txt")
+            .expect("failed to read test text file");
+        let mut sda = parse_sfdisk_full_disk(input).unwrap();
+        // Move the ESP to the end of the disk
+        sda.partitions[0].start_block = 976000000;
+
+        assert_eq!(sda.is_sorted_by(&SortKey::TypeGroups), Ok(true));
+        assert_eq!(sda.is_sorted(), Ok(false));
+
+        let renamings = sda.rearrange_by(&SortKey::TypeGroups).unwrap();
+        let old_new: Vec<(usize, usize)> = renamings
+            .iter()
+            .map(|r| (r.old_designation, r.new_designation))
+            .collect();
+        assert_eq!(old_new, vec![(1, 1), (2, 2), (3, 3), (4, 4)]);
+
+        let renamings = sda.rearrange().unwrap();
+        let old_new: Vec<(usize, usize)> = renamings
+            .iter()
+            .map(|r| (r.old_designation, r.new_designation))
+            .collect();
+        assert_eq!(old_new, vec![(2, 1), (3, 2), (4, 3), (1, 4)]);
+    }
+
+    #[test]
+    fn test_rearrange_any_device() {
+        for (device, ugly, pretty) in [
+            (
+                "/dev/loop0",
+                ["/dev/loop0p2", "/dev/loop0p1"],
+                ["/dev/loop0p1", "/dev/loop0p2"],
+            ),
+            (
+                "/dev/xvda",
+                ["/dev/xvda2", "/dev/xvda1"],
+                ["/dev/xvda1", "/dev/xvda2"],
+            ),
+            (
+                "/dev/md127",
+                ["/dev/md127p2", "/dev/md127p1"],
+                ["/dev/md127p1", "/dev/md127p2"],
+            ),
+            (
+                "/dev/dm-0",
+                ["/dev/dm-0p2", "/dev/dm-0p1"],
+                ["/dev/dm-0p1", "/dev/dm-0p2"],
+            ),
+            (
+                "/dev/disk/by-id/ata-XYZ",
+                [
+                    "/dev/disk/by-id/ata-XYZ-part2",
+                    "/dev/disk/by-id/ata-XYZ-part1",
+                ],
+                [
+                    "/dev/disk/by-id/ata-XYZ-part1",
+                    "/dev/disk/by-id/ata-XYZ-part2",
+                ],
+            ),
+            (
+                "/dev/disk/by-path/pci-0000:00:1f.2-ata-1",
+                [
+                    "/dev/disk/by-path/pci-0000:00:1f.2-ata-1-part2",
+                    "/dev/disk/by-path/pci-0000:00:1f.2-ata-1-part1",
+                ],
+                [
+                    "/dev/disk/by-path/pci-0000:00:1f.2-ata-1-part1",
+                    "/dev/disk/by-path/pci-0000:00:1f.2-ata-1-part2",
+                ],
+            ),
+        ] {
+            let input = format!(
+                "label: gpt\ndevice: {}\n\n{} : start=2048, size=2048\n{} : start=4096, size=2048\n",
+                device, ugly[0], ugly[1]
+            );
+            let mut this_disk = parse_sfdisk_full_disk(input).unwrap();
+            assert!(this_disk.validate().is_empty());
+
+            let renamings = this_disk.rearrange().unwrap();
+            let new_names: Vec<&str> =
+                renamings.iter().map(|r| r.new_name.as_str()).collect();
+            assert_eq!(new_names, pretty);
+        }
+    }
+
+    /// Rearranges the dump in `file` with `arrangement`, returning the old and new
+    /// designation of each partition.
+    fn rearranged(
+        file: &str,
+        arrangement: Arrangement,
+    ) -> Result<Vec<(usize, usize)>, LayoutError> {
+        let input = std::fs::read_to_string(file).expect("failed to read test text file");
+        let mut disk = parse_sfdisk_full_disk(input).unwrap();
+        disk.rearrange_with(&arrangement).map(|renamings| {
+            renamings
+                .iter()
+                .map(|r| (r.old_designation, r.new_designation))
+                .collect()
+        })
+    }
+
+    #[test]
+    fn test_rearrange_pinned() {
+        let pinned = |pins: &[&str]| {
+            rearranged(
+                "./assets/sfdisk_output_dos_ugly.txt",
+                Arrangement {
+                    pins: pins.iter().map(|pin| pin.parse().unwrap()).collect(),
+                    ..Default::default()
+                },
+            )
+        };
+
+        assert_eq!(
+            pinned(&[]),
+            Ok(vec![(2, 1), (3, 2), (1, 3), (6, 5), (5, 6)])
+        );
+        assert_eq!(
+            pinned(&["/dev/vda1=4", "/dev/vda5=5"]),
+            Ok(vec![(2, 1), (3, 2), (1, 4), (5, 5), (6, 6)])
+        );
+        assert_eq!(
+            pinned(&["/dev/vda1=1", "/dev/vda2=1"]),
+            Err(LayoutError::ConflictingPins {
+                designation: 1,
+                partitions: vec![String::from("/dev/vda1"), String::from("/dev/vda2")],
+            })
+        );
+        assert_eq!(
+            rearranged(
+                "./assets/sfdisk_output_ugly.txt",
+                Arrangement {
+                    pins: vec!["/dev/sda1=500".parse().unwrap()],
+                    ..Default::default()
+                },
+            ),
+            Err(LayoutError::PinOutOfRange {
+                partition: String::from("/dev/sda1"),
+                designation: 500,
+                first: 1,
+                last: Some(128),
+            })
+        );
+        assert_eq!(
+            pinned(&["/dev/vda5=7"]),
+            Err(LayoutError::PinOutOfRange {
+                partition: String::from("/dev/vda5"),
+                designation: 7,
+                first: 5,
+                last: Some(6),
+            })
+        );
+    }
+
+    #[test]
+    fn test_rearrange_numbering() {
+        let numbered = |file: &str, numbering: Numbering| {
+            rearranged(
+                file,
+                Arrangement {
+                    numbering,
+                    ..Default::default()
+                },
+            )
+        };
+        let starting_at = |first: usize, gaps: &[usize]| Numbering::Sequential {
+            first,
+            gaps: gaps.to_vec(),
+        };
+
+        let gpt = "./assets/sfdisk_output_ugly.txt";
+        assert_eq!(
+            numbered(gpt, Numbering::KeepUsed),
+            Ok(vec![(2, 1), (1, 2), (5, 4), (4, 5)])
+        );
+        assert_eq!(
+            numbered(gpt, starting_at(2, &[3])),
+            Ok(vec![(2, 2), (1, 4), (5, 5), (4, 6)])
+        );
+        assert_eq!(
+            numbered(gpt, starting_at(126, &[])),
+            Err(LayoutError::NoDesignationLeft {
+                partition: String::from("/dev/sda4"),
+                last: Some(128),
+            })
+        );
+
+        let dos = "./assets/sfdisk_output_dos_ugly.txt";
+        assert_eq!(
+            numbered(dos, starting_at(2, &[])),
+            Ok(vec![(2, 2), (3, 3), (1, 4), (6, 5), (5, 6)])
+        );
+        assert_eq!(
+            numbered(dos, starting_at(3, &[])),
+            Err(LayoutError::NoDesignationLeft {
+                partition: String::from("/dev/vda1"),
+                last: Some(4),
+            })
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let input = std::fs::read_to_string("./assets/sfdisk_output_dos_ugly.txt")
+            .expect("failed to read ugly DOS test text file");
+        let mut disk = parse_sfdisk_full_disk(input).unwrap();
+        let renamings = disk.rearrange().unwrap();
+
+        let stored = serde_json::to_string(&disk).unwrap();
+        let loaded: Disk = serde_json::from_str(&stored).unwrap();
+        assert_eq!(loaded, disk);
+        assert_eq!(loaded.name, "/dev/vda");
+        assert_eq!(loaded.header.to_string(), disk.header.to_string());
+        for (loaded_part, part) in loaded.partitions.iter().zip(&disk.partitions) {
+            assert_eq!(loaded_part.to_string(), part.to_string());
+        }
+
+        // The name follows the device, and a device named like a partition is refused
+        let moved = stored.replace("\"device\":\"/dev/vda\"", "\"device\":\"/dev/sdb\"");
+        let moved: Disk = serde_json::from_str(&moved).unwrap();
+        assert_eq!(moved.name, "/dev/sdb");
+        assert_eq!(
+            moved.linux_block_device,
+            Some(block::LinuxBlockDevice::SCSI)
+        );
+        let partition =
+            stored.replace("\"device\":\"/dev/vda\"", "\"device\":\"/dev/vda1\"");
+        assert!(serde_json::from_str::<Disk>(&partition).is_err());
+
+        let stored = serde_json::to_string(&renamings).unwrap();
+        let loaded: Vec<crate::partition::Renaming> =
+            serde_json::from_str(&stored).unwrap();
+        assert_eq!(loaded, renamings);
     }
 }
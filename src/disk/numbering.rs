@@ -0,0 +1,194 @@
+use crate::error::LayoutError;
+use crate::partition::Partition;
+
+/// Which designations `Disk::rearrange_with` gives the sorted partitions.
+/// Pinned partitions always keep their pinned designation.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Numbering {
+    /// Contiguously from `first`, leaving the `gaps` designations empty,
+    /// e.g. `first: 2` leaves room for a partition inserted later at 1
+    Sequential {
+        /// Designation of the first partition
+        first: usize,
+        /// Designations to skip
+        gaps: Vec<usize>,
+    },
+    /// The designations the partitions already use, reassigned in sorted order
+    KeepUsed,
+}
+
+impl Default for Numbering {
+    fn default() -> Self {
+        Self::Sequential {
+            first: 1,
+            gaps: Vec::new(),
+        }
+    }
+}
+
+impl Numbering {
+    /// Returns the designations for the unpinned partitions among `parts`, lowest first.
+    /// Designations before `lowest` are never used.
+    fn designations(
+        &self,
+        parts: &[Partition],
+        lowest: usize,
+    ) -> Box<dyn Iterator<Item = usize> + '_> {
+        match self {
+            Self::Sequential { first, gaps } => Box::new(
+                (*first.max(&lowest)..)
+                    .filter(move |designation| !gaps.contains(designation)),
+            ),
+            Self::KeepUsed => {
+                let mut used: Vec<usize> =
+                    parts.iter().map(|part| part.designation).collect();
+                used.sort_unstable();
+                used.dedup();
+                Box::new(used.into_iter())
+            }
+        }
+    }
+}
+
+/// Numbers the sorted `parts` with designations from `first` up to `last` (if bounded).
+/// Pinned partitions get their pinned designation, and the others take the lowest free
+/// designations of `numbering` in order.
+pub fn number(
+    parts: &[Partition],
+    pinned: &[(String, usize)],
+    numbering: &Numbering,
+    first: usize,
+    last: Option<usize>,
+) -> Result<Vec<usize>, LayoutError> {
+    let pin_of = |part: &Partition| {
+        pinned
+            .iter()
+            .find(|(name, _)| *name == part.name)
+            .map(|(_, designation)| *designation)
+    };
+
+    let mut taken: Vec<usize> = Vec::new();
+    for part in parts {
+        if let Some(designation) = pin_of(part) {
+            if designation < first || last.is_some_and(|last| designation > last) {
+                return Err(LayoutError::PinOutOfRange {
+                    partition: part.name.clone(),
+                    designation,
+                    first,
+                    last,
+                });
+            }
+            taken.push(designation);
+        }
+    }
+
+    let mut free = numbering
+        .designations(parts, first)
+        .filter(|designation| !taken.contains(designation));
+    let mut designations: Vec<usize> = Vec::with_capacity(parts.len());
+    for part in parts {
+        let designation = match pin_of(part) {
+            Some(designation) => designation,
+            None => match free.next() {
+                Some(designation) if last.is_none_or(|last| designation <= last) => {
+                    designation
+                }
+                _ => {
+                    return Err(LayoutError::NoDesignationLeft {
+                        partition: part.name.clone(),
+                        last,
+                    })
+                }
+            },
+        };
+        designations.push(designation);
+    }
+
+    Ok(designations)
+}
+
+#[cfg(test)]
+mod numbering_test {
+    use super::{number, Numbering};
+    use crate::disk::pin::pin_test::parts;
+    use crate::error::LayoutError;
+
+    #[test]
+    fn test_number() {
+        let parts = parts(&[1, 2, 3, 4]);
+        let pinned = vec![
+            (String::from("/dev/sda3"), 1),
+            (String::from("/dev/sda1"), 6),
+        ];
+        let default = Numbering::default();
+
+        assert_eq!(
+            number(&parts, &pinned, &default, 1, None),
+            Ok(vec![6, 2, 1, 3])
+        );
+        assert_eq!(
+            number(&parts, &[], &default, 5, Some(8)),
+            Ok(vec![5, 6, 7, 8])
+        );
+        assert_eq!(
+            number(&parts, &pinned, &default, 1, Some(4)),
+            Err(LayoutError::PinOutOfRange {
+                partition: String::from("/dev/sda1"),
+                designation: 6,
+                first: 1,
+                last: Some(4),
+            })
+        );
+    }
+
+    #[test]
+    fn test_numbering() {
+        let parts = parts(&[7, 2, 9, 4]);
+        let starting_at = |first: usize, gaps: &[usize]| Numbering::Sequential {
+            first,
+            gaps: gaps.to_vec(),
+        };
+
+        assert_eq!(
+            number(&parts, &[], &starting_at(2, &[]), 1, None),
+            Ok(vec![2, 3, 4, 5])
+        );
+        assert_eq!(
+            number(&parts, &[], &starting_at(1, &[2, 3]), 1, None),
+            Ok(vec![1, 4, 5, 6])
+        );
+        assert_eq!(
+            number(&parts, &[], &Numbering::KeepUsed, 1, None),
+            Ok(vec![2, 4, 7, 9])
+        );
+        // The pinned designation is not reused
+        let pinned = vec![(String::from("/dev/sda9"), 4)];
+        assert_eq!(
+            number(&parts, &pinned, &Numbering::KeepUsed, 1, None),
+            Ok(vec![2, 7, 4, 9])
+        );
+        assert_eq!(
+            number(&parts, &[], &starting_at(2, &[]), 1, Some(4)),
+            Err(LayoutError::NoDesignationLeft {
+                partition: String::from("/dev/sda4"),
+                last: Some(4),
+            })
+        );
+
+        let bounded = number(&parts, &[], &starting_at(2, &[]), 1, Some(4));
+        assert_eq!(
+            bounded.unwrap_err().to_string(),
+            "no partition number up to 4 is left for /dev/sda4"
+        );
+        let unbounded = LayoutError::NoDesignationLeft {
+            partition: String::from("/dev/sda4"),
+            last: None,
+        };
+        assert_eq!(
+            unbounded.to_string(),
+            "no partition number is left for /dev/sda4"
+        );
+    }
+}
@@ -0,0 +1,251 @@
+use crate::error::LayoutError;
+use crate::partition::Partition;
+
+/// Which partition a `Pin` applies to, matched against the partitions before sorting.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum PinTarget {
+    /// Current partition name, e.g. `/dev/mmcblk0p1`
+    Name(String),
+    /// Partition UUID (sfdisk `uuid=`), compared case-insensitively
+    Uuid(String),
+    /// GPT partition name (sfdisk `name=`)
+    Label(String),
+}
+
+impl PinTarget {
+    /// Reports whether `part` is the partition this target names.
+    pub fn matches(&self, part: &Partition) -> bool {
+        match self {
+            Self::Name(name) => &part.name == name,
+            Self::Uuid(uuid) => part
+                .uuid
+                .as_deref()
+                .is_some_and(|part_uuid| part_uuid.eq_ignore_ascii_case(uuid)),
+            Self::Label(label) => part.label.as_deref() == Some(label.as_str()),
+        }
+    }
+}
+
+impl std::fmt::Display for PinTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Name(name) => write!(f, "{}", name),
+            Self::Uuid(uuid) => write!(f, "uuid:{}", uuid),
+            Self::Label(label) => write!(f, "label:{}", label),
+        }
+    }
+}
+
+/// Keeps the partition matching `target` at `designation` when rearranging,
+/// while the other partitions are sorted into the remaining numbers.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Pin {
+    /// The pinned partition
+    pub target: PinTarget,
+    /// Number the partition keeps, e.g. 1 for `/dev/sda1`
+    pub designation: usize,
+}
+
+impl std::fmt::Display for Pin {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}={}", self.target, self.designation)
+    }
+}
+
+/// Parses the `--pin` values `NAME=N`, `uuid:UUID=N` and `label:LABEL=N`.
+impl std::str::FromStr for Pin {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (target, designation) = match s.rsplit_once('=') {
+            Some(split) => split,
+            None => {
+                return Err(format!(
+                    "invalid pin {}, expected NAME=N, uuid:UUID=N or label:LABEL=N",
+                    s
+                ))
+            }
+        };
+
+        let designation = match designation.parse::<usize>() {
+            Ok(n) if n > 0 => n,
+            _ => {
+                return Err(format!(
+                    "invalid partition number {} in pin {}",
+                    designation, s
+                ))
+            }
+        };
+
+        let target = if let Some(uuid) = target.strip_prefix("uuid:") {
+            PinTarget::Uuid(String::from(uuid))
+        } else if let Some(label) = target.strip_prefix("label:") {
+            PinTarget::Label(String::from(label))
+        } else {
+            PinTarget::Name(String::from(target))
+        };
+        let (PinTarget::Name(value) | PinTarget::Uuid(value) | PinTarget::Label(value)) =
+            &target;
+        if value.is_empty() {
+            return Err(format!("missing partition in pin {}", s));
+        }
+
+        Ok(Pin {
+            target,
+            designation,
+        })
+    }
+}
+
+/// Finds the partition of each pin, and returns their current names with the pinned
+/// designations. Each pin must match exactly one partition, and no two partitions can
+/// be pinned to the same designation.
+pub fn resolve(
+    partitions: &[Partition],
+    pins: &[Pin],
+) -> Result<Vec<(String, usize)>, LayoutError> {
+    let mut pinned: Vec<(String, usize)> = Vec::with_capacity(pins.len());
+
+    for pin in pins {
+        let matched: Vec<&Partition> = partitions
+            .iter()
+            .filter(|part| pin.target.matches(part))
+            .collect();
+
+        let name = match matched.as_slice() {
+            [] => return Err(LayoutError::PinNotFound(pin.to_string())),
+            [part] => part.name.clone(),
+            _ => {
+                return Err(LayoutError::AmbiguousPin {
+                    pin: pin.to_string(),
+                    partitions: matched.iter().map(|part| part.name.clone()).collect(),
+                })
+            }
+        };
+
+        for (other, designation) in &pinned {
+            if *other == name && *designation != pin.designation {
+                return Err(LayoutError::PinnedTwice {
+                    partition: name,
+                    first: *designation,
+                    second: pin.designation,
+                });
+            }
+            if *other != name && *designation == pin.designation {
+                return Err(LayoutError::ConflictingPins {
+                    designation: pin.designation,
+                    partitions: vec![other.clone(), name],
+                });
+            }
+        }
+
+        if !pinned.contains(&(name.clone(), pin.designation)) {
+            pinned.push((name, pin.designation));
+        }
+    }
+
+    Ok(pinned)
+}
+
+#[cfg(test)]
+pub(crate) mod pin_test {
+    use super::{resolve, Pin, PinTarget};
+    use crate::error::LayoutError;
+    use crate::linux::block;
+    use crate::partition::Partition;
+
+    /// Partitions of `/dev/sda` with the given designations, starting 2048 blocks apart,
+    /// with UUIDs after the designation and the labels `odd` and `even`.
+    pub(crate) fn parts(designations: &[usize]) -> Vec<Partition> {
+        designations
+            .iter()
+            .map(|designation| {
+                let designation = *designation;
+                let mut part = Partition::new_from_start_block(
+                    designation,
+                    designation as u64 * 2048,
+                    block::LinuxBlockDevice::SCSI,
+                );
+                part.uuid = Some(format!(
+                    "0000000{}-0000-0000-0000-000000000000",
+                    designation
+                ));
+                part.label = Some(String::from(if designation % 2 == 0 {
+                    "even"
+                } else {
+                    "odd"
+                }));
+                part
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!(
+            "/dev/mmcblk0p1=1".parse(),
+            Ok(Pin {
+                target: PinTarget::Name(String::from("/dev/mmcblk0p1")),
+                designation: 1,
+            })
+        );
+        assert_eq!(
+            "label:a=b=3".parse(),
+            Ok(Pin {
+                target: PinTarget::Label(String::from("a=b")),
+                designation: 3,
+            })
+        );
+        for invalid in ["/dev/sda1", "/dev/sda1=0", "uuid:=1", "=2", "label:x=y"] {
+            assert!(
+                invalid.parse::<Pin>().is_err(),
+                "{} should be rejected",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        let parts = parts(&[1, 2, 3, 4]);
+        let pin = |s: &str| s.parse::<Pin>().unwrap();
+
+        assert_eq!(
+            resolve(
+                &parts,
+                &[pin("uuid:00000003-0000-0000-0000-000000000000=1")]
+            ),
+            Ok(vec![(String::from("/dev/sda3"), 1)])
+        );
+        assert_eq!(
+            resolve(&parts, &[pin("/dev/sda9=1")]),
+            Err(LayoutError::PinNotFound(String::from("/dev/sda9=1")))
+        );
+        assert_eq!(
+            resolve(&parts, &[pin("label:even=1")]),
+            Err(LayoutError::AmbiguousPin {
+                pin: String::from("label:even=1"),
+                partitions: vec![String::from("/dev/sda2"), String::from("/dev/sda4")],
+            })
+        );
+        assert_eq!(
+            resolve(&parts, &[pin("/dev/sda1=2"), pin("/dev/sda3=2")]),
+            Err(LayoutError::ConflictingPins {
+                designation: 2,
+                partitions: vec![String::from("/dev/sda1"), String::from("/dev/sda3")],
+            })
+        );
+        assert_eq!(
+            resolve(&parts, &[pin("/dev/sda1=2"), pin("/dev/sda1=3")]),
+            Err(LayoutError::PinnedTwice {
+                partition: String::from("/dev/sda1"),
+                first: 2,
+                second: 3,
+            })
+        );
+    }
+}
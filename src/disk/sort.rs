@@ -0,0 +1,204 @@
+use crate::partition::{Partition, PartitionType};
+
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+/// EFI system partition
+const ESP_GUID: &str = "C12A7328-F81F-11D2-BA4B-00A0C93EC93B";
+/// Linux swap
+const SWAP_GUID: &str = "0657FD6D-A4AB-43C4-84E5-0933C84B4F4F";
+/// Linux filesystem data, what sfdisk writes for `type=L` on GPT
+const LINUX_GUID: &str = "0FC63DAF-8483-4772-8E79-3D69D8477DE4";
+/// Root partitions of the Discoverable Partitions Specification, for x86, x86-64, ARM and AArch64
+const ROOT_GUIDS: [&str; 4] = [
+    "44479540-F297-41B2-9AF7-D131D5F0458A",
+    "4F68BCE3-E8CD-4DB1-96E7-FBCAF984B709",
+    "69DAD710-2CE4-4E3C-B16C-21A1D49ABED3",
+    "B921B045-1DF0-41C3-AF44-4C6F280D3FAE",
+];
+
+/// User-supplied ordering for `SortKey::Custom`.
+pub type Comparator = Arc<dyn Fn(&Partition, &Partition) -> Ordering + Send + Sync>;
+
+/// How `Disk::rearrange_by` orders partitions before numbering them.
+/// Partitions that compare equal keep their start block order.
+#[derive(Clone, Default)]
+#[non_exhaustive]
+pub enum SortKey {
+    /// By start block, i.e. the on-disk order
+    #[default]
+    StartBlock,
+    /// By current designation, so that only the holes in the numbering are closed
+    Designation,
+    /// By GPT partition name (sfdisk `name=`), unnamed partitions last
+    Name,
+    /// ESP first, then root, then swap, then everything else
+    TypeGroups,
+    /// By a comparator of the caller
+    Custom(Comparator),
+}
+
+impl SortKey {
+    /// Compares two partitions by this key, then by start block.
+    pub fn compare(&self, a: &Partition, b: &Partition) -> Ordering {
+        let ordering = match self {
+            Self::StartBlock => Ordering::Equal,
+            Self::Designation => a.designation.cmp(&b.designation),
+            Self::Name => {
+                (a.label.is_none(), &a.label).cmp(&(b.label.is_none(), &b.label))
+            }
+            Self::TypeGroups => type_group(a).cmp(&type_group(b)),
+            Self::Custom(comparator) => comparator(a, b),
+        };
+
+        ordering.then(a.start_block.cmp(&b.start_block))
+    }
+}
+
+/// Returns 0 for ESP, 1 for root, 2 for swap and 3 for everything else.
+/// The generic Linux types count as root, as they are what sfdisk writes for `L`: `83`
+/// on MBR, which has no root type, and the Linux filesystem GUID on GPT.
+fn type_group(part: &Partition) -> u8 {
+    match &part.part_type {
+        Some(PartitionType::Gpt(guid)) if guid.eq_ignore_ascii_case(ESP_GUID) => 0,
+        Some(PartitionType::Gpt(guid))
+            if guid.eq_ignore_ascii_case(LINUX_GUID)
+                || ROOT_GUIDS.iter().any(|root| guid.eq_ignore_ascii_case(root)) =>
+        {
+            1
+        }
+        Some(PartitionType::Gpt(guid)) if guid.eq_ignore_ascii_case(SWAP_GUID) => 2,
+        Some(PartitionType::Mbr(0xef)) => 0,
+        Some(PartitionType::Mbr(0x83)) => 1,
+        Some(PartitionType::Mbr(0x82)) => 2,
+        // sfdisk shortcuts and aliases
+        Some(PartitionType::Alias(alias)) => match alias.to_lowercase().as_str() {
+            "u" | "uefi" => 0,
+            "l" | "linux" => 1,
+            "s" | "swap" => 2,
+            _ => 3,
+        },
+        _ => 3,
+    }
+}
+
+impl std::fmt::Debug for SortKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::StartBlock => write!(f, "StartBlock"),
+            Self::Designation => write!(f, "Designation"),
+            Self::Name => write!(f, "Name"),
+            Self::TypeGroups => write!(f, "TypeGroups"),
+            Self::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+/// Custom keys are only equal to themselves.
+impl PartialEq for SortKey {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Custom(a), Self::Custom(b)) => Arc::ptr_eq(a, b),
+            _ => std::mem::discriminant(self) == std::mem::discriminant(other),
+        }
+    }
+}
+
+impl Eq for SortKey {}
+
+/// Parses the `--sort-by` names `start`, `designation`, `name` and `type`.
+impl std::str::FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "start" => Ok(Self::StartBlock),
+            "designation" => Ok(Self::Designation),
+            "name" => Ok(Self::Name),
+            "type" => Ok(Self::TypeGroups),
+            _ => Err(format!(
+                "unknown sort key {}, expected start, designation, name or type",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod sort_test {
+    use super::{SortKey, ESP_GUID, LINUX_GUID, SWAP_GUID};
+    use crate::linux::block;
+    use crate::partition::{Partition, PartitionType};
+
+    use std::sync::Arc;
+
+    fn part(
+        designation: usize,
+        start: u64,
+        part_type: &str,
+        label: Option<&str>,
+    ) -> Partition {
+        let mut part = Partition::new_from_start_block(
+            designation,
+            start,
+            block::LinuxBlockDevice::SCSI,
+        );
+        part.part_type = Some(PartitionType::parse(part_type));
+        part.label = label.map(String::from);
+
+        part
+    }
+
+    fn sorted_designations(key: SortKey) -> Vec<usize> {
+        let mut parts = [
+            part(4, 1000, SWAP_GUID, Some("a-swap")),
+            part(
+                2,
+                3000,
+                "4F68BCE3-E8CD-4DB1-96E7-FBCAF984B709",
+                Some("root"),
+            ),
+            part(7, 2000, LINUX_GUID, None),
+            part(1, 4000, ESP_GUID, Some("efi")),
+        ];
+        parts.sort_by(|a, b| key.compare(a, b));
+
+        parts.iter().map(|part| part.designation).collect()
+    }
+
+    #[test]
+    fn test_compare() {
+        assert_eq!(sorted_designations(SortKey::StartBlock), vec![4, 7, 2, 1]);
+        assert_eq!(sorted_designations(SortKey::Designation), vec![1, 2, 4, 7]);
+        assert_eq!(sorted_designations(SortKey::Name), vec![4, 1, 2, 7]);
+        // The Linux filesystem GUID is root too, and comes first by start block
+        assert_eq!(sorted_designations(SortKey::TypeGroups), vec![1, 7, 2, 4]);
+
+        let reversed =
+            SortKey::Custom(Arc::new(|a, b| b.start_block.cmp(&a.start_block)));
+        assert_eq!(sorted_designations(reversed), vec![1, 2, 7, 4]);
+    }
+
+    #[test]
+    fn test_type_groups_dos() {
+        let mut parts = [
+            part(1, 1000, "82", None),
+            part(2, 2000, "7", None),
+            part(3, 3000, "83", None),
+            part(4, 4000, "ef", None),
+            part(5, 5000, "S", None),
+            part(6, 6000, "L", None),
+        ];
+        parts.sort_by(|a, b| SortKey::TypeGroups.compare(a, b));
+
+        let designations: Vec<usize> =
+            parts.iter().map(|part| part.designation).collect();
+        assert_eq!(designations, vec![4, 3, 6, 1, 5, 2]);
+    }
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!("type".parse(), Ok(SortKey::TypeGroups));
+        assert!("size".parse::<SortKey>().is_err());
+    }
+}
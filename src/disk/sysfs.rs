@@ -0,0 +1,118 @@
+use super::validate::Diagnostic;
+use super::Disk;
+use crate::error::SysfsError;
+use crate::linux::block::{Sysfs, SYSFS_SECTOR_SIZE};
+use crate::linux::naming;
+
+impl Disk {
+    /// Compares the partitions with the running kernel's view of the disk in `sysfs`.
+    /// Any difference means the dump is stale, or the kernel has not re-read the table
+    /// since it changed. Partitions are matched by number, and the sizes of DOS extended
+    /// partitions are not compared, as the kernel only maps their first sectors.
+    pub fn validate_sysfs(&self, sysfs: &Sysfs) -> Result<Vec<Diagnostic>, SysfsError> {
+        let kernel_parts = sysfs.partitions(&self.name)?;
+        // sysfs counts 512-byte sectors, the dump counts `sector-size:` sectors. A disk
+        // built with a sector size of 0 is compared in 512-byte sectors.
+        let to_dump_sectors = |sectors: u64| {
+            sectors
+                .saturating_mul(SYSFS_SECTOR_SIZE)
+                .checked_div(self.sector_size())
+                .unwrap_or(sectors)
+        };
+
+        let mut diagnostics: Vec<Diagnostic> = Vec::new();
+        for part in &self.partitions {
+            let kernel_part = match kernel_parts
+                .iter()
+                .find(|kernel_part| kernel_part.designation == part.designation)
+            {
+                Some(kernel_part) => kernel_part,
+                None => {
+                    diagnostics.push(Diagnostic::NotInKernel {
+                        partition: part.name.clone(),
+                    });
+                    continue;
+                }
+            };
+
+            let kernel_start = to_dump_sectors(kernel_part.start);
+            let kernel_size = to_dump_sectors(kernel_part.size);
+            let size_differs =
+                !part.is_extended() && part.size.is_some_and(|size| size != kernel_size);
+            if part.start_block != kernel_start || size_differs {
+                diagnostics.push(Diagnostic::KernelMismatch {
+                    partition: part.name.clone(),
+                    start: part.start_block,
+                    size: part.size,
+                    kernel_start,
+                    kernel_size,
+                });
+            }
+        }
+
+        for kernel_part in &kernel_parts {
+            if !self
+                .partitions
+                .iter()
+                .any(|part| part.designation == kernel_part.designation)
+            {
+                diagnostics.push(Diagnostic::NotInDump {
+                    partition: naming::part_name(&self.name, kernel_part.designation),
+                });
+            }
+        }
+
+        Ok(diagnostics)
+    }
+}
+
+#[cfg(test)]
+mod sysfs_test {
+    use crate::disk::validate::Diagnostic;
+    use crate::disk::{parse_sfdisk_full_disk, Disk};
+    use crate::error::SysfsError;
+    use crate::linux::block::Sysfs;
+
+    #[test]
+    fn test_validate_sysfs() {
+        let input = std::fs::read_to_string("./assets/sfdisk_output.txt")
+            .expect("failed to read test text file");
+        let sda = parse_sfdisk_full_disk(input).unwrap();
+        let sysfs = Sysfs::new("./assets/sysfs_block");
+
+        // The kernel has a shrunk sda3, and sda5 instead of sda4
+        assert_eq!(
+            sda.validate_sysfs(&sysfs).unwrap(),
+            vec![
+                Diagnostic::KernelMismatch {
+                    partition: String::from("/dev/sda3"),
+                    start: 67520512,
+                    size: Some(33554432),
+                    kernel_start: 67520512,
+                    kernel_size: 16777216,
+                },
+                Diagnostic::NotInKernel {
+                    partition: String::from("/dev/sda4"),
+                },
+                Diagnostic::NotInDump {
+                    partition: String::from("/dev/sda5"),
+                },
+            ]
+        );
+
+        let mut header = sda.header().clone();
+        header.sector_size = Some(0);
+        let zero_sectors = Disk::new(header, sda.partitions().to_vec()).unwrap();
+        assert_eq!(
+            zero_sectors.validate_sysfs(&sysfs).unwrap(),
+            sda.validate_sysfs(&sysfs).unwrap()
+        );
+
+        let mut sdb = sda.clone();
+        sdb.name = String::from("/dev/sdb");
+        assert!(matches!(
+            sdb.validate_sysfs(&sysfs),
+            Err(SysfsError::NoDevice { .. })
+        ));
+    }
+}
@@ -0,0 +1,366 @@
+use super::align::Misalignment;
+use super::Disk;
+use crate::linux::naming;
+use crate::partition::Partition;
+
+/// A problem in the partition layout that would make `sfdisk` reject the table,
+/// or make it corrupt data if applied.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[non_exhaustive]
+pub enum Diagnostic {
+    /// Two partitions share at least one block
+    Overlap {
+        /// The partition listed first
+        first: String,
+        /// The partition listed later
+        second: String,
+    },
+    /// Partition starts before the header's `first-lba`
+    BeforeFirstLba {
+        /// Name of the partition
+        partition: String,
+        /// Start block of the partition
+        start: u64,
+        /// `first-lba` of the header
+        first_lba: u64,
+    },
+    /// Partition ends after the header's `last-lba`
+    AfterLastLba {
+        /// Name of the partition
+        partition: String,
+        /// Last block of the partition, or its start block if it has no end
+        end: u64,
+        /// `last-lba` of the header
+        last_lba: u64,
+    },
+    /// Partition has `size= 0`
+    ZeroSize {
+        /// Name of the partition
+        partition: String,
+    },
+    /// Partition whose last block is past the largest block number
+    EndOverflow {
+        /// Name of the partition
+        partition: String,
+        /// Start block of the partition
+        start: u64,
+        /// Size of the partition, in sectors
+        size: u64,
+    },
+    /// Two partitions have the same `uuid=`
+    DuplicateUuid {
+        /// The shared UUID
+        uuid: String,
+        /// The first partition with the UUID
+        first: String,
+        /// The partition repeating it
+        second: String,
+    },
+    /// Partition is named after another disk than the `device:` of its dump, e.g.
+    /// `/dev/sdb1` under `device: /dev/sda`
+    ForeignPartition {
+        /// Name of the partition
+        partition: String,
+        /// The `device:` of the dump
+        device: String,
+    },
+    /// Partition start or size is not aligned, only reported by `Disk::validate_aligned`
+    Misaligned(Misalignment),
+    /// Partition of the dump that the kernel does not have, only reported by
+    /// `Disk::validate_sysfs` like the other differences from the kernel's view
+    NotInKernel {
+        /// Name of the partition
+        partition: String,
+    },
+    /// Partition the kernel has but the dump does not
+    NotInDump {
+        /// Name the kernel partition would have under the disk of the dump
+        partition: String,
+    },
+    /// Partition with another start or size in the kernel than in the dump, in sectors
+    /// of the dump
+    KernelMismatch {
+        /// Name of the partition
+        partition: String,
+        /// Start block in the dump
+        start: u64,
+        /// Size in the dump
+        size: Option<u64>,
+        /// Start block in the kernel
+        kernel_start: u64,
+        /// Size in the kernel
+        kernel_size: u64,
+    },
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Overlap { first, second } => {
+                write!(f, "partitions {} and {} overlap", first, second)
+            }
+            Self::BeforeFirstLba {
+                partition,
+                start,
+                first_lba,
+            } => write!(
+                f,
+                "partition {} starts at {}, before first-lba {}",
+                partition, start, first_lba
+            ),
+            Self::AfterLastLba {
+                partition,
+                end,
+                last_lba,
+            } => write!(
+                f,
+                "partition {} ends at {}, after last-lba {}",
+                partition, end, last_lba
+            ),
+            Self::ZeroSize { partition } => {
+                write!(f, "partition {} has zero size", partition)
+            }
+            Self::EndOverflow {
+                partition,
+                start,
+                size,
+            } => write!(
+                f,
+                "partition {} starts at {} with size {}, which ends past the last block",
+                partition, start, size
+            ),
+            Self::DuplicateUuid {
+                uuid,
+                first,
+                second,
+            } => write!(
+                f,
+                "partitions {} and {} have the same uuid {}",
+                first, second, uuid
+            ),
+            Self::ForeignPartition { partition, device } => {
+                write!(
+                    f,
+                    "partition {} does not belong to device {}",
+                    partition, device
+                )
+            }
+            Self::Misaligned(misalignment) => write!(f, "{}", misalignment),
+            Self::NotInKernel { partition } => {
+                write!(
+                    f,
+                    "partition {} is not in the kernel's partition table",
+                    partition
+                )
+            }
+            Self::NotInDump { partition } => {
+                write!(
+                    f,
+                    "the kernel has partition {}, which is not in the dump",
+                    partition
+                )
+            }
+            Self::KernelMismatch {
+                partition,
+                start,
+                size,
+                kernel_start,
+                kernel_size,
+            } => {
+                write!(f, "partition {} starts at {}", partition, start)?;
+                if let Some(size) = size {
+                    write!(f, " with size {}", size)?;
+                }
+                write!(
+                    f,
+                    ", but the kernel has it at {} with size {}",
+                    kernel_start, kernel_size
+                )
+            }
+        }
+    }
+}
+
+impl Disk {
+    /// Checks the partition layout for overlaps, out-of-bounds partitions, zero-size
+    /// partitions, partitions ending past the largest block number, duplicate UUIDs and
+    /// partitions of other disks.
+    /// An empty result means the layout is valid.
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut diagnostics: Vec<Diagnostic> = Vec::new();
+
+        for (i, part) in self.partitions.iter().enumerate() {
+            if !naming::is_part_of(&self.name, &part.name) {
+                diagnostics.push(Diagnostic::ForeignPartition {
+                    partition: part.name.clone(),
+                    device: self.name.clone(),
+                });
+            }
+
+            if part.size == Some(0) {
+                diagnostics.push(Diagnostic::ZeroSize {
+                    partition: part.name.clone(),
+                });
+            }
+
+            if let (Some(size), None) = (part.size, part.end_block()) {
+                diagnostics.push(Diagnostic::EndOverflow {
+                    partition: part.name.clone(),
+                    start: part.start_block,
+                    size,
+                });
+            }
+
+            if let Some(first_lba) = self.header.first_lba {
+                if part.start_block < first_lba {
+                    diagnostics.push(Diagnostic::BeforeFirstLba {
+                        partition: part.name.clone(),
+                        start: part.start_block,
+                        first_lba,
+                    });
+                }
+            }
+            if let Some(last_lba) = self.header.last_lba {
+                // Without an end, the partition is after last-lba if its start is
+                let end = part.end_block().unwrap_or(part.start_block);
+                if end > last_lba {
+                    diagnostics.push(Diagnostic::AfterLastLba {
+                        partition: part.name.clone(),
+                        end,
+                        last_lba,
+                    });
+                }
+            }
+
+            for other in self.partitions.iter().skip(i + 1) {
+                if overlaps(part, other) {
+                    diagnostics.push(Diagnostic::Overlap {
+                        first: part.name.clone(),
+                        second: other.name.clone(),
+                    });
+                }
+
+                if let (Some(uuid), Some(other_uuid)) = (&part.uuid, &other.uuid) {
+                    if uuid.eq_ignore_ascii_case(other_uuid) {
+                        diagnostics.push(Diagnostic::DuplicateUuid {
+                            uuid: uuid.clone(),
+                            first: part.name.clone(),
+                            second: other.name.clone(),
+                        });
+                    }
+                }
+            }
+        }
+
+        diagnostics
+    }
+}
+
+/// Reports whether `a` and `b` share blocks. Partitions without size are never
+/// reported, and neither are logical partitions inside their DOS extended container.
+fn overlaps(a: &Partition, b: &Partition) -> bool {
+    let (a_end, b_end) = match (a.end_block(), b.end_block()) {
+        (Some(a_end), Some(b_end)) => (a_end, b_end),
+        _ => return false,
+    };
+    if a.size == Some(0) || b.size == Some(0) {
+        return false;
+    }
+
+    let contains =
+        |outer: &Partition, outer_end: u64, inner: &Partition, inner_end: u64| {
+            outer.is_extended()
+                && !inner.is_extended()
+                && inner.start_block > outer.start_block
+                && inner_end <= outer_end
+        };
+    if contains(a, a_end, b, b_end) || contains(b, b_end, a, a_end) {
+        return false;
+    }
+
+    a.start_block <= b_end && b.start_block <= a_end
+}
+
+#[cfg(test)]
+mod validate_test {
+    use super::Diagnostic;
+    use crate::disk::parse_sfdisk_full_disk;
+
+    #[test]
+    fn test_validate_ok() {
+        for file in [
+            "./assets/sfdisk_output_ugly.txt",
+            "./assets/sfdisk_output_dos_ugly.txt",
+        ] {
+            let input =
+                std::fs::read_to_string(file).expect("failed to read test text file");
+            let disk = parse_sfdisk_full_disk(input).unwrap();
+            assert_eq!(disk.validate(), vec![]);
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let input = "label: gpt
+device: /dev/sda
+first-lba: 2048
+last-lba: 100000
+
+/dev/sda1 : start=        1024, size=        2048, uuid=AAAAAAAA-BBBB-CCCC-DDDD-EEEEEEEEEEEE
+/dev/sda2 : start=        3000, size=        1000
+/dev/sda3 : start=       50000, size=           0
+/dev/sda4 : start=       90000, size=       20000, uuid=aaaaaaaa-bbbb-cccc-dddd-eeeeeeeeeeee
+/dev/sdb5 : start=       60000, size=        1000
+/dev/sda6 : start=18446744073709551000, size=        1000
+/dev/sda7 : start=      200000
+";
+        let disk = parse_sfdisk_full_disk(String::from(input)).unwrap();
+
+        assert_eq!(
+            disk.validate(),
+            vec![
+                Diagnostic::BeforeFirstLba {
+                    partition: String::from("/dev/sda1"),
+                    start: 1024,
+                    first_lba: 2048,
+                },
+                Diagnostic::Overlap {
+                    first: String::from("/dev/sda1"),
+                    second: String::from("/dev/sda2"),
+                },
+                Diagnostic::DuplicateUuid {
+                    uuid: String::from("AAAAAAAA-BBBB-CCCC-DDDD-EEEEEEEEEEEE"),
+                    first: String::from("/dev/sda1"),
+                    second: String::from("/dev/sda4"),
+                },
+                Diagnostic::ZeroSize {
+                    partition: String::from("/dev/sda3")
+                },
+                Diagnostic::AfterLastLba {
+                    partition: String::from("/dev/sda4"),
+                    end: 109999,
+                    last_lba: 100000,
+                },
+                Diagnostic::ForeignPartition {
+                    partition: String::from("/dev/sdb5"),
+                    device: String::from("/dev/sda"),
+                },
+                Diagnostic::EndOverflow {
+                    partition: String::from("/dev/sda6"),
+                    start: 18446744073709551000,
+                    size: 1000,
+                },
+                Diagnostic::AfterLastLba {
+                    partition: String::from("/dev/sda6"),
+                    end: 18446744073709551000,
+                    last_lba: 100000,
+                },
+                Diagnostic::AfterLastLba {
+                    partition: String::from("/dev/sda7"),
+                    end: 200000,
+                    last_lba: 100000,
+                },
+            ]
+        );
+    }
+}
@@ -1,17 +1,571 @@
-#[derive(Debug)]
-pub struct RegexError;
-impl std::error::Error for RegexError {}
-impl std::fmt::Display for RegexError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "text does not match regex")
+use thiserror::Error;
+
+use std::ops::Range;
+use std::path::PathBuf;
+
+/// Where a problem is in the input, for pointing at it.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+#[non_exhaustive]
+pub struct Span {
+    /// 1-based line number
+    pub line: usize,
+    /// Byte range of the offending text in `text`
+    pub columns: Range<usize>,
+    /// The whole input line
+    pub text: String,
+}
+
+impl Span {
+    /// Points at the bytes `columns` of `text`, line `line` of the input.
+    pub fn new(line: usize, columns: Range<usize>, text: &str) -> Self {
+        Span {
+            line,
+            columns,
+            text: String::from(text),
+        }
+    }
+
+    /// Returns the offending text.
+    pub fn snippet(&self) -> &str {
+        self.text.get(self.columns.clone()).unwrap_or_default()
+    }
+
+    /// Points just past the last line of `input`, for things missing from the whole input.
+    pub fn end_of(input: &str) -> Self {
+        Span::new(input.lines().count() + 1, 0..0, "")
+    }
+
+    /// Points at the bytes `range` of `input`, on the line they start on.
+    pub fn in_input(input: &str, range: Range<usize>) -> Self {
+        let line_start = input[..range.start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = input[range.start..]
+            .find('\n')
+            .map_or(input.len(), |i| range.start + i);
+        let end = range.end.min(line_end);
+        Span::new(
+            input[..range.start].matches('\n').count() + 1,
+            range.start - line_start..end - line_start,
+            &input[line_start..line_end],
+        )
+    }
+}
+
+/// Where a parser found the name of each disk and partition in its input, for pointing
+/// at the culprit of a `LayoutError`.
+#[derive(Debug, Default, Clone)]
+pub struct NameSpans(Vec<(String, Span)>);
+
+impl NameSpans {
+    /// Records that `name` was found at `span`.
+    pub fn insert(&mut self, name: &str, span: Span) {
+        self.0.push((String::from(name), span));
+    }
+
+    /// Returns where `name` was first found.
+    pub fn get(&self, name: &str) -> Option<&Span> {
+        self.0
+            .iter()
+            .find(|(found, _)| found == name)
+            .map(|(_, span)| span)
+    }
+
+    /// Adds the names found in `other`, e.g. another dump of the same input.
+    pub fn extend(&mut self, other: &NameSpans) {
+        self.0.extend(other.0.iter().cloned());
+    }
+
+    /// Moves every span `offset` lines down, for a dump that is a part of the input.
+    pub(crate) fn offset_lines(&mut self, offset: usize) {
+        for (_, span) in &mut self.0 {
+            span.line += offset;
+        }
+    }
+}
+
+/// Errors in the `sfdisk -d` or `sfdisk -J` input. Parsers of a single line or field
+/// leave `Span::line` at 0, and the caller fills it in with `ParseError::on_line`.
+#[derive(Debug, Error, PartialEq, Eq, Clone)]
+#[non_exhaustive]
+pub enum ParseError {
+    /// A line that is neither a header, a partition, a comment nor blank
+    #[error("unrecognized text `{}`", .0.snippet())]
+    UnrecognizedLine(Span),
+    /// A number that does not fit in the field, or is not a number at all
+    #[error("invalid number `{}`", .0.snippet())]
+    InvalidNumber(Span),
+    /// A `sector-size:` the kernel does not support
+    #[error("invalid sector size `{}`, expected a power of two of at least 512", .0.snippet())]
+    InvalidSectorSize(Span),
+    /// A quoted field value without its closing quote
+    #[error("unterminated quote `{}`", .0.snippet())]
+    UnterminatedQuote(Span),
+    /// Text between a quoted field value and the next comma
+    #[error("unexpected text `{}` after field value", .0.snippet())]
+    TrailingText(Span),
+    /// A backslash at the end of a quoted value, or `\x` without 2 hex digits
+    #[error("invalid escape `{}`", .0.snippet())]
+    InvalidEscape(Span),
+    /// Escaped bytes of a quoted value that do not decode as UTF-8
+    #[error("quoted value `{}` is not valid UTF-8", .0.snippet())]
+    InvalidUtf8(Span),
+    /// A `label:` other than `gpt` and `dos`
+    #[error("unsupported partition table label `{}`", .0.snippet())]
+    UnknownLabel(Span),
+    /// A `device:` with whitespace in it
+    #[error("invalid device name `{}`", .0.snippet())]
+    InvalidDevice(Span),
+    /// A `device:` that names a partition, or ends in `/`
+    #[error("device `{}` does not name a disk", .0.snippet())]
+    UnsupportedDevice(Span),
+    /// A partition name that does not end in a partition number of a disk
+    #[error("missing partition number in `{}`", .0.snippet())]
+    MissingPartitionNumber(Span),
+    /// A partition numbered 0, while partitions are numbered from 1
+    #[error("invalid partition number `{}`, partitions are numbered from 1", .0.snippet())]
+    ZeroPartitionNumber(Span),
+    /// A dump without a `device:` line, pointing past the end of the input
+    #[error("missing device line")]
+    MissingDevice(Span),
+    /// JSON that is malformed or not shaped like `sfdisk -J` output
+    #[error("invalid sfdisk JSON: {1}")]
+    Json(Span, String),
+    /// A layout problem of the parsed disk, at the name of its culprit
+    #[error("{1}")]
+    Layout(Span, LayoutError),
+}
+
+impl ParseError {
+    /// Returns where the error is in the input.
+    pub fn span(&self) -> &Span {
+        match self {
+            Self::UnrecognizedLine(span)
+            | Self::InvalidNumber(span)
+            | Self::InvalidSectorSize(span)
+            | Self::UnterminatedQuote(span)
+            | Self::TrailingText(span)
+            | Self::InvalidEscape(span)
+            | Self::InvalidUtf8(span)
+            | Self::UnknownLabel(span)
+            | Self::InvalidDevice(span)
+            | Self::UnsupportedDevice(span)
+            | Self::MissingPartitionNumber(span)
+            | Self::ZeroPartitionNumber(span)
+            | Self::MissingDevice(span)
+            | Self::Json(span, _)
+            | Self::Layout(span, _) => span,
+        }
+    }
+
+    fn span_mut(&mut self) -> &mut Span {
+        match self {
+            Self::UnrecognizedLine(span)
+            | Self::InvalidNumber(span)
+            | Self::InvalidSectorSize(span)
+            | Self::UnterminatedQuote(span)
+            | Self::TrailingText(span)
+            | Self::InvalidEscape(span)
+            | Self::InvalidUtf8(span)
+            | Self::UnknownLabel(span)
+            | Self::InvalidDevice(span)
+            | Self::UnsupportedDevice(span)
+            | Self::MissingPartitionNumber(span)
+            | Self::ZeroPartitionNumber(span)
+            | Self::MissingDevice(span)
+            | Self::Json(span, _)
+            | Self::Layout(span, _) => span,
+        }
+    }
+
+    /// Sets the line number of the error.
+    pub fn on_line(mut self, line: usize) -> Self {
+        self.span_mut().line = line;
+        self
+    }
+
+    /// Moves an error found in a part of `text` starting at byte `offset` onto `text`.
+    pub fn within(mut self, offset: usize, text: &str) -> Self {
+        let span = self.span_mut();
+        span.columns = span.columns.start + offset..span.columns.end + offset;
+        span.text = String::from(text);
+        self
+    }
+
+    /// Moves the error onto `span`, for text that was reformatted before parsing (e.g.
+    /// JSON values parsed as header lines).
+    pub fn at(mut self, span: Span) -> Self {
+        *self.span_mut() = span;
+        self
+    }
+}
+
+/// Errors in the partition layout that make it impossible to rearrange.
+/// The partitions are named instead of located, see `LayoutError::span`.
+#[derive(Debug, Error, PartialEq, Eq, Clone)]
+#[non_exhaustive]
+pub enum LayoutError {
+    /// The extended partitions of a DOS disk, when there are more than 1
+    #[error("DOS disk can only have 1 extended partition, found {}", .0.len())]
+    TooManyExtended(Vec<String>),
+    /// An extended partition without `size=`, so its logicals cannot be told apart
+    #[error("extended partition {0} has no size")]
+    ExtendedWithoutSize(String),
+    /// A logical partition, numbered 5 or above, that no extended partition contains
+    #[error("logical partition {0} is outside of any extended partition")]
+    LogicalOutsideExtended(String),
+    /// More primary and extended partitions than a DOS partition table holds
+    #[error("DOS disk can only have {max} primary partitions, found {}", .partitions.len())]
+    TooManyPrimaries {
+        /// Most primary partitions the disk can have
+        max: usize,
+        /// The primary and extended partitions
+        partitions: Vec<String>,
+    },
+    /// A device name that names a partition, or ends in `/`
+    #[error("device {0} does not name a disk")]
+    UnsupportedDevice(String),
+    /// A partition whose name does not follow from the name of its disk
+    #[error("partition {partition} is not named like a partition of {device}")]
+    PartitionName {
+        /// Name of the disk
+        device: String,
+        /// Name of the partition
+        partition: String,
+    },
+    /// A pin, as given on the command line, that matches no partition
+    #[error("pin {0} matches no partition")]
+    PinNotFound(String),
+    /// A pin that matches more than 1 partition
+    #[error("pin {pin} matches {} partitions: {}", .partitions.len(), .partitions.join(", "))]
+    AmbiguousPin {
+        /// The pin, as given on the command line
+        pin: String,
+        /// The partitions it matches
+        partitions: Vec<String>,
+    },
+    /// Partitions pinned to the same designation
+    #[error("partitions {} are both pinned to {designation}", .partitions.join(" and "))]
+    ConflictingPins {
+        /// The designation pinned twice
+        designation: usize,
+        /// The partitions pinned to it
+        partitions: Vec<String>,
+    },
+    /// A partition pinned to 2 designations
+    #[error("partition {partition} is pinned to both {first} and {second}")]
+    PinnedTwice {
+        /// Name of the partition
+        partition: String,
+        /// The first designation it is pinned to
+        first: usize,
+        /// The other designation it is pinned to
+        second: usize,
+    },
+    /// A pin to a designation the numbering or partition table cannot give
+    #[error("partition {partition} cannot be pinned to {designation}, {}", pin_range(*.first, *.last))]
+    PinOutOfRange {
+        /// Name of the partition
+        partition: String,
+        /// The designation it is pinned to
+        designation: usize,
+        /// Lowest designation allowed
+        first: usize,
+        /// Highest designation allowed, if the table is bounded
+        last: Option<usize>,
+    },
+    /// A partition left without a designation, e.g. when gaps use up a GPT table
+    #[error("{} for {partition}", no_designation_left(*.last))]
+    NoDesignationLeft {
+        /// Name of the partition
+        partition: String,
+        /// Highest designation of the table, if it has one
+        last: Option<usize>,
+    },
+    /// An alignment in bytes that is not a whole number of sectors
+    #[error("alignment of {bytes} bytes is not a multiple of the {sector_size}-byte sectors of {device}")]
+    PartialSectorAlignment {
+        /// Name of the disk
+        device: String,
+        /// The alignment
+        bytes: u64,
+        /// Sector size of the disk, in bytes
+        sector_size: u64,
+    },
+    /// A disk with a sector size of 0, which nothing can be aligned to
+    #[error("device {device} has a sector size of {sector_size} bytes")]
+    InvalidSectorSize {
+        /// Name of the disk
+        device: String,
+        /// Sector size of the disk, in bytes
+        sector_size: u64,
+    },
+}
+
+/// Describes the designations a pinned partition can take, for `LayoutError::PinOutOfRange`.
+fn pin_range(first: usize, last: Option<usize>) -> String {
+    match last {
+        Some(last) => format!("it must be numbered {}-{}", first, last),
+        None => format!("it must be numbered from {}", first),
+    }
+}
+
+/// Describes the designations that ran out, for `LayoutError::NoDesignationLeft`.
+fn no_designation_left(last: Option<usize>) -> String {
+    match last {
+        Some(last) => format!("no partition number up to {} is left", last),
+        None => String::from("no partition number is left"),
+    }
+}
+
+impl LayoutError {
+    /// Returns the name of the first offending partition or device.
+    pub fn culprit(&self) -> &str {
+        match self {
+            Self::TooManyExtended(names)
+            | Self::TooManyPrimaries {
+                partitions: names, ..
+            }
+            | Self::ConflictingPins {
+                partitions: names, ..
+            } => names.last().map(String::as_str).unwrap_or_default(),
+            Self::AmbiguousPin {
+                partitions: names, ..
+            } => names.first().map(String::as_str).unwrap_or_default(),
+            Self::ExtendedWithoutSize(name)
+            | Self::LogicalOutsideExtended(name)
+            | Self::UnsupportedDevice(name)
+            | Self::PinNotFound(name)
+            | Self::PartitionName {
+                partition: name, ..
+            }
+            | Self::PinnedTwice {
+                partition: name, ..
+            }
+            | Self::PinOutOfRange {
+                partition: name, ..
+            }
+            | Self::NoDesignationLeft {
+                partition: name, ..
+            }
+            | Self::PartialSectorAlignment { device: name, .. }
+            | Self::InvalidSectorSize { device: name, .. } => name,
+        }
     }
+
+    /// Locates the offending partition or device where the parser found its name.
+    pub fn span(&self, spans: &NameSpans) -> Option<Span> {
+        spans.get(self.culprit()).cloned()
+    }
+}
+
+/// Errors reading the partitions of a disk from sysfs, see `linux::block::Sysfs`.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum SysfsError {
+    /// A disk without a directory in the sysfs tree
+    #[error("device {device} is not in {path}")]
+    NoDevice {
+        /// Name of the disk
+        device: String,
+        /// The sysfs block directory
+        path: PathBuf,
+    },
+    /// A sysfs file or link that could not be read
+    #[error("failed to read {path}")]
+    Read {
+        /// The file or link
+        path: PathBuf,
+        /// Why it could not be read
+        #[source]
+        source: std::io::Error,
+    },
+    /// A sysfs file that does not hold a number
+    #[error("invalid value `{value}` in {path}")]
+    InvalidValue {
+        /// The file
+        path: PathBuf,
+        /// Its content
+        value: String,
+    },
 }
 
-#[derive(Debug)]
-pub struct RegexCapturesError;
-impl std::error::Error for RegexCapturesError {}
-impl std::fmt::Display for RegexCapturesError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "regex capture failed")
+/// Errors reading or writing the GPT of a disk image, see `gpt::read_gpt` and
+/// `gpt::write::write_gpt`.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum GptError {
+    /// The image could not be read or written
+    #[error("failed to read or write the disk image")]
+    Io(#[from] std::io::Error),
+    /// Sector 0 does not hold an MBR with a GPT protective partition
+    #[error("no protective MBR in sector 0")]
+    NoProtectiveMbr,
+    /// No GPT header at any supported sector size
+    #[error("no GPT header signature `EFI PART` in sector 1")]
+    NoHeader,
+    /// A header field that is out of range, e.g. usable LBAs past the image
+    #[error("invalid GPT header: {0}")]
+    InvalidHeader(String),
+    /// The header does not match its CRC32
+    #[error("GPT header CRC32 is {actual:08x}, expected {expected:08x}")]
+    HeaderCrc {
+        /// CRC32 stored in the header
+        expected: u32,
+        /// CRC32 of the header as read
+        actual: u32,
+    },
+    /// The partition entries do not match their CRC32
+    #[error("GPT partition entries CRC32 is {actual:08x}, expected {expected:08x}")]
+    EntriesCrc {
+        /// CRC32 stored in the header
+        expected: u32,
+        /// CRC32 of the entries as read
+        actual: u32,
+    },
+    /// An entry ending before it starts
+    #[error("GPT partition entry {number} ends in LBA {last_lba}, before its start {first_lba}")]
+    InvalidEntry {
+        /// Partition number of the entry
+        number: usize,
+        /// First LBA of the entry
+        first_lba: u64,
+        /// Last LBA of the entry
+        last_lba: u64,
+    },
+    /// A partition of the disk that is in no entry of the image
+    #[error("partition {0} does not match any entry of the GPT in the disk image")]
+    NotInImage(String),
+    /// An entry of the image that is not a partition of the disk
+    #[error("partition {0} of the GPT in the disk image is missing from the disk")]
+    MissingFromDisk(String),
+    /// Partitions of the disk with the same number
+    #[error("partition {partition} has number {designation}, like another partition")]
+    DesignationTaken {
+        /// Name of the partition
+        partition: String,
+        /// The number it shares
+        designation: usize,
+    },
+    /// A partition numbered 0, which has no entry
+    #[error("partition {0} has number 0, GPT partitions are numbered from 1")]
+    ZeroDesignation(String),
+    /// A partition numbered past the entry array
+    #[error(
+        "partition {partition} does not fit in the {entry_count} GPT partition entries"
+    )]
+    TooFewEntries {
+        /// Name of the partition
+        partition: String,
+        /// Number of entries in the array
+        entry_count: u32,
+    },
+    /// The disk cannot be rearranged
+    #[error(transparent)]
+    Layout(#[from] LayoutError),
+}
+
+/// Formats an error pointing at `span` in the input named `source`, the way compilers do:
+///
+/// ```text
+/// error: invalid number `12x`
+///  --> sda.dump:3:20
+///   |
+/// 3 | /dev/sda1 : start= 12x
+///   |                    ^^^
+/// ```
+pub fn render(message: &str, span: &Span, source: &str) -> String {
+    let number = span.line.to_string();
+    let gutter = " ".repeat(number.len());
+    let start = span.text[..span.columns.start.min(span.text.len())]
+        .chars()
+        .count();
+    let width = span.snippet().chars().count().max(1);
+
+    format!(
+        "error: {}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}\n",
+        message,
+        gutter,
+        source,
+        span.line,
+        start + 1,
+        gutter,
+        number,
+        span.text,
+        gutter,
+        " ".repeat(start),
+        "^".repeat(width)
+    )
+}
+
+#[cfg(test)]
+mod error_test {
+    use super::{render, LayoutError, ParseError, Span};
+    use crate::disk::parse_sfdisk_disks;
+
+    #[test]
+    fn test_render() {
+        let text = "/dev/sda1 : start= 12x";
+        let err = ParseError::InvalidNumber(Span::new(0, 19..22, text)).on_line(3);
+
+        assert_eq!(err.to_string(), "invalid number `12x`");
+        assert_eq!(
+            render(&err.to_string(), err.span(), "sda.dump"),
+            "error: invalid number `12x`
+ --> sda.dump:3:20
+  |
+3 | /dev/sda1 : start= 12x
+  |                    ^^^
+"
+        );
+    }
+
+    #[test]
+    fn test_within() {
+        let line = "/dev/sda1 : start= 2048, size=x";
+        let err =
+            ParseError::InvalidNumber(Span::new(0, 1..7, " size=x")).within(24, line);
+
+        assert_eq!(err.span().snippet(), "size=x");
+        assert_eq!(err.span().columns, 25..31);
+    }
+
+    #[test]
+    fn test_layout_span() {
+        let input =
+            "# /dev/sda1 was resized\ndevice: /dev/sda\n\n/dev/sda1 : start= 2048\n\
+                     /dev/sda10 : start= 4096\ndevice:  /dev/sdb\n";
+        let disks = parse_sfdisk_disks(String::from(input)).unwrap();
+        let err = LayoutError::ExtendedWithoutSize(String::from("/dev/sda1"));
+
+        // The partition line, not the comment mentioning it
+        assert_eq!(
+            err.span(disks[0].spans()),
+            Some(Span::new(4, 0..9, "/dev/sda1 : start= 2048"))
+        );
+        // Lines are counted from the start of the whole input
+        assert_eq!(
+            LayoutError::UnsupportedDevice(String::from("/dev/sdb"))
+                .span(disks[1].spans()),
+            Some(Span::new(6, 9..17, "device:  /dev/sdb"))
+        );
+        assert_eq!(
+            LayoutError::LogicalOutsideExtended(String::from("/dev/sdb5"))
+                .span(disks[0].spans()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_span_in_input() {
+        let input = "first\nsecond line\nthird";
+
+        assert_eq!(
+            Span::in_input(input, 13..17),
+            Span::new(2, 7..11, "second line")
+        );
+        // A range past the end of its line stops there
+        assert_eq!(
+            Span::in_input(input, 6..20),
+            Span::new(2, 0..11, "second line")
+        );
     }
 }
@@ -0,0 +1,108 @@
+//! Rewriting the device paths of `/etc/fstab` after partitions were renumbered.
+//!
+//! Sorting a partition table renames the partitions, so fstab entries naming them by
+//! kernel path, e.g. `/dev/sda5`, point at another partition afterwards. `rewrite`
+//! takes the renamings returned by `Disk::rearrange` and renames those paths:
+//!
+//! ```no_run
+//! let dump = std::fs::read_to_string("sda.dump").unwrap();
+//! let mut disk = sfdisk_sort_rs::parse_sfdisk_full_disk(dump).unwrap();
+//! let renamings = disk.rearrange().unwrap();
+//!
+//! let fstab = std::fs::read_to_string("/etc/fstab").unwrap();
+//! print!("{}", sfdisk_sort_rs::fstab::rewrite(&fstab, &renamings));
+//! ```
+//!
+//! Entries naming partitions by `UUID=`, `LABEL=` or `PARTUUID=` need no rewriting, as
+//! sorting keeps those.
+
+use crate::partition::Renaming;
+
+use std::collections::HashMap;
+
+/// Rewrites raw device paths (e.g. `/dev/sda5`) in the first column of fstab `content`
+/// to the new partition names in `renamings`. All renamings are applied at once,
+/// so swapped partitions do not get renamed twice.
+///
+/// Comments, blank lines, `UUID=`/`LABEL=`/`PARTUUID=` entries and devices that were not
+/// renamed are kept byte-for-byte, including whitespace.
+pub fn rewrite(content: &str, renamings: &[Renaming]) -> String {
+    let new_names: HashMap<&str, &str> = renamings
+        .iter()
+        .filter(|renaming| renaming.old_name != renaming.new_name)
+        .map(|renaming| (renaming.old_name.as_str(), renaming.new_name.as_str()))
+        .collect();
+
+    content
+        .split_inclusive('\n')
+        .map(|line| rewrite_line(line, &new_names))
+        .collect()
+}
+
+fn rewrite_line(line: &str, new_names: &HashMap<&str, &str>) -> String {
+    let trimmed = line.trim_start();
+    if trimmed.is_empty() || trimmed.starts_with('#') {
+        return String::from(line);
+    }
+
+    let indent = line.len() - trimmed.len();
+    let device_len = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+    let device = &trimmed[..device_len];
+
+    match new_names.get(device) {
+        Some(new_name) => {
+            format!("{}{}{}", &line[..indent], new_name, &trimmed[device_len..])
+        }
+        None => String::from(line),
+    }
+}
+
+#[cfg(test)]
+mod fstab_test {
+    use super::rewrite;
+    use crate::partition::Renaming;
+
+    fn renaming(old: &str, new: &str) -> Renaming {
+        Renaming {
+            old_name: String::from(old),
+            old_designation: 0,
+            new_name: String::from(new),
+            new_designation: 0,
+            uuid: None,
+        }
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let fstab = "# /dev/sda1 is the ESP
+/dev/sda1\t/boot\tvfat\tdefaults\t0 2
+  /dev/sda2   /      ext4   defaults   0 1
+UUID=AAAA-BBBB  /home  ext4  defaults  0 2
+LABEL=swap      none   swap  sw        0 0
+/dev/sda10      /srv   xfs   defaults  0 2
+/dev/sdb1       /mnt   ext4  defaults  0 2
+
+";
+        let renamings = vec![
+            renaming("/dev/sda2", "/dev/sda1"),
+            renaming("/dev/sda1", "/dev/sda2"),
+            renaming("/dev/sda10", "/dev/sda10"),
+        ];
+
+        assert_eq!(
+            rewrite(fstab, &renamings),
+            "# /dev/sda1 is the ESP
+/dev/sda2\t/boot\tvfat\tdefaults\t0 2
+  /dev/sda1   /      ext4   defaults   0 1
+UUID=AAAA-BBBB  /home  ext4  defaults  0 2
+LABEL=swap      none   swap  sw        0 0
+/dev/sda10      /srv   xfs   defaults  0 2
+/dev/sdb1       /mnt   ext4  defaults  0 2
+
+"
+        );
+
+        // No trailing newline
+        assert_eq!(rewrite("/dev/sda1 / ext4", &renamings), "/dev/sda2 / ext4");
+    }
+}
@@ -0,0 +1,683 @@
+use crate::disk::header::{DiskHeader, Label, DEFAULT_TABLE_LENGTH};
+use crate::disk::Disk;
+use crate::error::GptError;
+use crate::linux::naming;
+use crate::partition::{Partition, PartitionType};
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+pub(crate) mod write;
+
+/// What the GPT header starts with.
+pub const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+/// MBR partition type of the protective partition that covers a GPT disk.
+pub const PROTECTIVE_MBR_TYPE: u8 = 0xee;
+/// Logical sector sizes tried, in order, when looking for the GPT header in sector 1.
+pub const SECTOR_SIZES: [u64; 2] = [512, 4096];
+
+/// Size of the header fields defined by the UEFI specification.
+const MIN_HEADER_SIZE: u32 = 92;
+/// Size of the entry fields defined by the UEFI specification.
+const MIN_ENTRY_SIZE: u32 = 128;
+/// Largest entry array read, far more than the 16 KiB of a default table.
+const MAX_ENTRIES_BYTES: u64 = 4 * 1024 * 1024;
+
+/// Names sfdisk gives the GPT attribute bits 0-2, in the `attrs=` field.
+const ATTRIBUTE_NAMES: [&str; 3] = [
+    "RequiredPartition",
+    "NoBlockIOProtocol",
+    "LegacyBIOSBootable",
+];
+/// First of the bits the partition type defines, written by sfdisk as `GUID:N`.
+const FIRST_TYPE_ATTRIBUTE: u32 = 48;
+
+/// The fields of a GPT header, see the UEFI specification. LBAs are in sectors of
+/// `sector_size`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[non_exhaustive]
+pub struct GptHeader {
+    /// Sector size the header was found with, in bytes
+    pub sector_size: u64,
+    /// Size of the header, in bytes
+    pub header_size: u32,
+    /// CRC32 of the header, computed with this field zeroed
+    pub header_crc32: u32,
+    /// LBA of this header
+    pub current_lba: u64,
+    /// LBA of the other header
+    pub backup_lba: u64,
+    /// First LBA partitions can use
+    pub first_usable_lba: u64,
+    /// Last LBA partitions can use
+    pub last_usable_lba: u64,
+    /// Disk GUID, as sfdisk prints it in `label-id:`
+    pub disk_guid: String,
+    /// First LBA of the partition entry array
+    pub entries_lba: u64,
+    /// Number of entries in the array, used or not
+    pub entry_count: u32,
+    /// Size of each entry, in bytes
+    pub entry_size: u32,
+    /// CRC32 of the entry array
+    pub entries_crc32: u32,
+}
+
+/// A used slot of the GPT partition entry array.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[non_exhaustive]
+pub struct GptEntry {
+    /// Position in the entry array, from 0. The partition number is `slot + 1`
+    pub slot: usize,
+    /// Partition type GUID
+    pub type_guid: String,
+    /// Partition UUID
+    pub unique_guid: String,
+    /// First sector of the partition
+    pub first_lba: u64,
+    /// Last sector of the partition, inclusive
+    pub last_lba: u64,
+    /// Attribute bits, written by sfdisk as `attrs=`
+    pub attributes: u64,
+    /// Partition name, written by sfdisk as `name=`
+    pub name: String,
+}
+
+/// The primary GPT of a disk, with the used entries in slot order.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[non_exhaustive]
+pub struct Gpt {
+    /// The primary header
+    pub header: GptHeader,
+    /// The used entries of the primary entry array
+    pub entries: Vec<GptEntry>,
+}
+
+/// Reads the GPT of the disk image or block device at `path` into a Disk, named
+/// after `path` like `sfdisk -d` does, e.g. `disk.img1` for the partitions of `disk.img`.
+pub fn read_gpt_disk(path: impl AsRef<Path>) -> Result<Disk, GptError> {
+    let path = path.as_ref();
+    let mut image = File::open(path)?;
+
+    read_gpt(&mut image)?.to_disk(&path.to_string_lossy())
+}
+
+/// Reads the protective MBR, the primary GPT header and its partition entry array from
+/// `image`, checking the signatures and both CRC32s. The sector size is the first of
+/// `SECTOR_SIZES` with the GPT header signature in sector 1.
+pub fn read_gpt<R: Read + Seek>(image: &mut R) -> Result<Gpt, GptError> {
+    let mbr = read_at(image, 0, 512)?;
+    if !is_protective_mbr(&mbr) {
+        return Err(GptError::NoProtectiveMbr);
+    }
+
+    let mut found: Option<(u64, Vec<u8>)> = None;
+    for sector_size in SECTOR_SIZES {
+        let sector = match read_at(image, sector_size, sector_size as usize) {
+            Ok(sector) => sector,
+            // An image too small for this sector size
+            Err(GptError::Io(_)) => continue,
+            Err(err) => return Err(err),
+        };
+        if sector.starts_with(GPT_SIGNATURE) {
+            found = Some((sector_size, sector));
+            break;
+        }
+    }
+    let (sector_size, sector) = found.ok_or(GptError::NoHeader)?;
+
+    let header = parse_header(&sector, sector_size)?;
+    let image_len = image.seek(SeekFrom::End(0))?;
+    check_header(&header, 1, image_len)?;
+    let entries_bytes = read_entries(image, &header)?;
+    let entries: Vec<GptEntry> = entries_bytes
+        .chunks_exact(header.entry_size as usize)
+        .enumerate()
+        .filter_map(|(slot, bytes)| parse_entry(slot, bytes))
+        .collect();
+    for entry in &entries {
+        entry.size()?;
+    }
+
+    Ok(Gpt { header, entries })
+}
+
+impl Gpt {
+    /// Converts the GPT into the Disk that `sfdisk -d device` would print.
+    pub fn to_disk(&self, device: &str) -> Result<Disk, GptError> {
+        let header = DiskHeader {
+            label: Some(Label::Gpt),
+            label_id: Some(self.header.disk_guid.clone()),
+            device: String::from(device),
+            unit: Some(String::from("sectors")),
+            first_lba: Some(self.header.first_usable_lba),
+            last_lba: Some(self.header.last_usable_lba),
+            table_length: (self.header.entry_count as u64 != DEFAULT_TABLE_LENGTH)
+                .then_some(self.header.entry_count as u64),
+            sector_size: Some(self.header.sector_size),
+            ..Default::default()
+        };
+
+        let partitions = self
+            .entries
+            .iter()
+            .map(|entry| {
+                let designation = entry.slot + 1;
+                Ok(Partition {
+                    designation,
+                    start_block: entry.first_lba,
+                    name: naming::part_name(device, designation),
+                    size: Some(entry.size()?),
+                    part_type: Some(PartitionType::parse(&entry.type_guid)),
+                    uuid: Some(entry.unique_guid.clone()),
+                    label: (!entry.name.is_empty()).then(|| entry.name.clone()),
+                    attrs: attribute_names(entry.attributes),
+                    ..Default::default()
+                })
+            })
+            .collect::<Result<Vec<Partition>, GptError>>()?;
+
+        Ok(Disk::new(header, partitions)?)
+    }
+}
+
+impl GptHeader {
+    /// Returns the byte offset of `lba`, or an error if it is past the largest offset.
+    pub fn offset(&self, lba: u64) -> Result<u64, GptError> {
+        lba.checked_mul(self.sector_size).ok_or_else(|| {
+            GptError::InvalidHeader(format!(
+                "LBA {} is past the largest byte offset",
+                lba
+            ))
+        })
+    }
+
+    /// Returns the size of the partition entry array in bytes.
+    pub fn entries_len(&self) -> u64 {
+        self.entry_count as u64 * self.entry_size as u64
+    }
+}
+
+impl GptEntry {
+    /// Returns the number of sectors of the partition, or an error if it ends before it
+    /// starts.
+    pub fn size(&self) -> Result<u64, GptError> {
+        self.last_lba
+            .checked_sub(self.first_lba)
+            .and_then(|size| size.checked_add(1))
+            .ok_or(GptError::InvalidEntry {
+                number: self.slot + 1,
+                first_lba: self.first_lba,
+                last_lba: self.last_lba,
+            })
+    }
+}
+
+/// Reports whether the MBR sector has the boot signature and a partition of type `ee`.
+fn is_protective_mbr(mbr: &[u8]) -> bool {
+    mbr[510..512] == [0x55, 0xaa]
+        && (0..4).any(|i| mbr[446 + i * 16 + 4] == PROTECTIVE_MBR_TYPE)
+}
+
+/// Parses and checks the GPT header in `sector`.
+pub(crate) fn parse_header(
+    sector: &[u8],
+    sector_size: u64,
+) -> Result<GptHeader, GptError> {
+    if !sector.starts_with(GPT_SIGNATURE) {
+        return Err(GptError::NoHeader);
+    }
+
+    let header_size = le_u32(sector, 12);
+    if header_size < MIN_HEADER_SIZE || header_size as u64 > sector_size {
+        return Err(GptError::InvalidHeader(format!(
+            "header size {} is not between {} and {}",
+            header_size, MIN_HEADER_SIZE, sector_size
+        )));
+    }
+    let header_crc32 = le_u32(sector, 16);
+    let actual = header_crc(&sector[..header_size as usize]);
+    if actual != header_crc32 {
+        return Err(GptError::HeaderCrc {
+            expected: header_crc32,
+            actual,
+        });
+    }
+
+    let header = GptHeader {
+        sector_size,
+        header_size,
+        header_crc32,
+        current_lba: le_u64(sector, 24),
+        backup_lba: le_u64(sector, 32),
+        first_usable_lba: le_u64(sector, 40),
+        last_usable_lba: le_u64(sector, 48),
+        disk_guid: guid_to_string(&sector[56..72]),
+        entries_lba: le_u64(sector, 72),
+        entry_count: le_u32(sector, 80),
+        entry_size: le_u32(sector, 84),
+        entries_crc32: le_u32(sector, 88),
+    };
+    if header.entry_size < MIN_ENTRY_SIZE
+        || !header.entry_size.is_multiple_of(MIN_ENTRY_SIZE)
+    {
+        return Err(GptError::InvalidHeader(format!(
+            "partition entry size {} is not a multiple of {}",
+            header.entry_size, MIN_ENTRY_SIZE
+        )));
+    }
+    if header.entries_len() > MAX_ENTRIES_BYTES {
+        return Err(GptError::InvalidHeader(format!(
+            "{} partition entries of {} bytes are too many",
+            header.entry_count, header.entry_size
+        )));
+    }
+
+    Ok(header)
+}
+
+/// Checks that `header`, read from `lba` of an image of `image_len` bytes, points into
+/// the image: at itself, at a backup header and usable LBAs within the image, and at an
+/// entry array outside the usable LBAs.
+pub(crate) fn check_header(
+    header: &GptHeader,
+    lba: u64,
+    image_len: u64,
+) -> Result<(), GptError> {
+    let sectors = image_len / header.sector_size;
+    if header.current_lba != lba {
+        return Err(GptError::InvalidHeader(format!(
+            "the header in LBA {} says it is in LBA {}",
+            lba, header.current_lba
+        )));
+    }
+    if header.backup_lba >= sectors || header.backup_lba == lba {
+        return Err(GptError::InvalidHeader(format!(
+            "the other header in LBA {} is not elsewhere in the {} sectors of the image",
+            header.backup_lba, sectors
+        )));
+    }
+    if header.first_usable_lba > header.last_usable_lba
+        || header.last_usable_lba >= sectors
+    {
+        return Err(GptError::InvalidHeader(format!(
+            "usable LBAs {}-{} are not within the {} sectors of the image",
+            header.first_usable_lba, header.last_usable_lba, sectors
+        )));
+    }
+
+    let entries_sectors = header.entries_len().div_ceil(header.sector_size);
+    let entries_end = header.entries_lba.checked_add(entries_sectors);
+    let outside_usable = entries_end.is_some_and(|end| end <= header.first_usable_lba)
+        || header.entries_lba > header.last_usable_lba;
+    if entries_end.is_none_or(|end| end > sectors) || !outside_usable {
+        return Err(GptError::InvalidHeader(format!(
+            "partition entries in LBA {} are not outside the usable LBAs {}-{} of the \
+             image",
+            header.entries_lba, header.first_usable_lba, header.last_usable_lba
+        )));
+    }
+
+    Ok(())
+}
+
+/// Reads the partition entry array of `header`, and checks its CRC32.
+pub(crate) fn read_entries<R: Read + Seek>(
+    image: &mut R,
+    header: &GptHeader,
+) -> Result<Vec<u8>, GptError> {
+    let len = header.entries_len() as usize;
+    let bytes = read_at(image, header.offset(header.entries_lba)?, len)?;
+
+    let actual = crc32fast::hash(&bytes);
+    if actual != header.entries_crc32 {
+        return Err(GptError::EntriesCrc {
+            expected: header.entries_crc32,
+            actual,
+        });
+    }
+
+    Ok(bytes)
+}
+
+/// Parses the entry at `slot`, or returns None if the slot is unused (zero type GUID).
+fn parse_entry(slot: usize, bytes: &[u8]) -> Option<GptEntry> {
+    if bytes[0..16].iter().all(|byte| *byte == 0) {
+        return None;
+    }
+
+    let name_units: Vec<u16> = bytes[56..128]
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .take_while(|unit| *unit != 0)
+        .collect();
+
+    Some(GptEntry {
+        slot,
+        type_guid: guid_to_string(&bytes[0..16]),
+        unique_guid: guid_to_string(&bytes[16..32]),
+        first_lba: le_u64(bytes, 32),
+        last_lba: le_u64(bytes, 40),
+        attributes: le_u64(bytes, 48),
+        name: String::from_utf16_lossy(&name_units),
+    })
+}
+
+/// Returns the CRC32 of the `header` bytes, computed with its own CRC32 field zeroed.
+pub(crate) fn header_crc(header: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&header[..16]);
+    hasher.update(&[0; 4]);
+    hasher.update(&header[20..]);
+    hasher.finalize()
+}
+
+/// Formats the attribute bits the way sfdisk writes them in `attrs=`, e.g.
+/// `RequiredPartition GUID:63`. Bits 3-47 are reserved and sfdisk ignores them.
+fn attribute_names(attributes: u64) -> Vec<String> {
+    let mut names: Vec<String> = ATTRIBUTE_NAMES
+        .iter()
+        .enumerate()
+        .filter(|(bit, _)| attributes & (1 << bit) != 0)
+        .map(|(_, name)| String::from(*name))
+        .collect();
+
+    let type_bits: Vec<String> = (FIRST_TYPE_ATTRIBUTE..64)
+        .filter(|bit| attributes & (1 << bit) != 0)
+        .map(|bit| bit.to_string())
+        .collect();
+    if !type_bits.is_empty() {
+        names.push(format!("GUID:{}", type_bits.join(",")));
+    }
+
+    names
+}
+
+/// Formats an on-disk GUID, whose first three fields are little-endian, in the upper
+/// case `8-4-4-4-12` form sfdisk prints.
+pub(crate) fn guid_to_string(bytes: &[u8]) -> String {
+    format!(
+        "{:08X}-{:04X}-{:04X}-{}-{}",
+        le_u32(bytes, 0),
+        u16::from_le_bytes([bytes[4], bytes[5]]),
+        u16::from_le_bytes([bytes[6], bytes[7]]),
+        hex(&bytes[8..10]),
+        hex(&bytes[10..16])
+    )
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+fn le_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn le_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// Reads `len` bytes of `image` from byte `offset`.
+pub(crate) fn read_at<R: Read + Seek>(
+    image: &mut R,
+    offset: u64,
+    len: usize,
+) -> Result<Vec<u8>, GptError> {
+    let mut bytes = vec![0; len];
+    image.seek(SeekFrom::Start(offset))?;
+    image.read_exact(&mut bytes)?;
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+pub(crate) mod gpt_test {
+    use super::{header_crc, read_gpt, read_gpt_disk, GPT_SIGNATURE};
+    use crate::disk::parse_sfdisk_full_disk;
+    use crate::error::GptError;
+    use crate::partition::PartitionType;
+
+    use std::io::Cursor;
+
+    /// Sectors of the test images, 2 MiB with 512-byte sectors.
+    pub(crate) const TEST_SECTORS: u64 = 4096;
+
+    /// A partition of a test image: type GUID bytes, unique GUID bytes, first LBA,
+    /// last LBA, attributes and name.
+    pub(crate) type TestEntry = ([u8; 16], [u8; 16], u64, u64, u64, &'static str);
+
+    pub(crate) fn guid(byte: u8) -> [u8; 16] {
+        [byte; 16]
+    }
+
+    /// Builds a disk image with a protective MBR, and a primary and backup GPT of 128
+    /// entries holding `entries` in the given slots.
+    pub(crate) fn image(sector_size: u64, entries: &[(usize, TestEntry)]) -> Vec<u8> {
+        let ss = sector_size as usize;
+        let sectors = TEST_SECTORS * 512 / sector_size;
+        let mut image = vec![0u8; sectors as usize * ss];
+
+        image[446 + 4] = 0xee;
+        image[510] = 0x55;
+        image[511] = 0xaa;
+
+        let mut array = vec![0u8; 128 * 128];
+        for (slot, (type_guid, unique_guid, first, last, attributes, name)) in entries {
+            let entry = &mut array[slot * 128..(slot + 1) * 128];
+            entry[0..16].copy_from_slice(type_guid);
+            entry[16..32].copy_from_slice(unique_guid);
+            entry[32..40].copy_from_slice(&first.to_le_bytes());
+            entry[40..48].copy_from_slice(&last.to_le_bytes());
+            entry[48..56].copy_from_slice(&attributes.to_le_bytes());
+            for (i, unit) in name.encode_utf16().enumerate() {
+                entry[56 + i * 2..58 + i * 2].copy_from_slice(&unit.to_le_bytes());
+            }
+        }
+        let array_sectors = (array.len() / ss) as u64;
+
+        let mut write_gpt = |lba: u64, backup_lba: u64, entries_lba: u64| {
+            let at = entries_lba as usize * ss;
+            image[at..at + array.len()].copy_from_slice(&array);
+
+            let mut header = vec![0u8; 92];
+            header[0..8].copy_from_slice(GPT_SIGNATURE);
+            header[8..12].copy_from_slice(&[0, 0, 1, 0]);
+            header[12..16].copy_from_slice(&92u32.to_le_bytes());
+            header[24..32].copy_from_slice(&lba.to_le_bytes());
+            header[32..40].copy_from_slice(&backup_lba.to_le_bytes());
+            header[40..48].copy_from_slice(&(2 + array_sectors).to_le_bytes());
+            header[48..56].copy_from_slice(&(sectors - 2 - array_sectors).to_le_bytes());
+            header[56..72].copy_from_slice(&guid(0x12));
+            header[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+            header[80..84].copy_from_slice(&128u32.to_le_bytes());
+            header[84..88].copy_from_slice(&128u32.to_le_bytes());
+            header[88..92].copy_from_slice(&crc32fast::hash(&array).to_le_bytes());
+            let crc = header_crc(&header);
+            header[16..20].copy_from_slice(&crc.to_le_bytes());
+
+            let at = lba as usize * ss;
+            image[at..at + header.len()].copy_from_slice(&header);
+        };
+        write_gpt(1, sectors - 1, 2);
+        write_gpt(sectors - 1, 1, sectors - 1 - array_sectors);
+
+        image
+    }
+
+    pub(crate) fn test_entries() -> Vec<(usize, TestEntry)> {
+        vec![
+            (0, (guid(0xaf), guid(0x01), 2048, 3071, 0, "data")),
+            (
+                2,
+                (
+                    guid(0xef),
+                    guid(0x02),
+                    34,
+                    2047,
+                    1 | (1 << 63),
+                    "EFI system",
+                ),
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_read_gpt() {
+        let image = image(512, &test_entries());
+        let gpt = read_gpt(&mut Cursor::new(image)).unwrap();
+
+        assert_eq!(gpt.header.sector_size, 512);
+        assert_eq!(gpt.header.first_usable_lba, 34);
+        assert_eq!(gpt.header.last_usable_lba, 4062);
+        assert_eq!(gpt.header.backup_lba, 4095);
+        assert_eq!(gpt.entries.len(), 2);
+        assert_eq!(gpt.entries[1].slot, 2);
+        assert_eq!(gpt.entries[1].name, "EFI system");
+
+        let disk = gpt.to_disk("disk.img").unwrap();
+        assert_eq!(
+            disk.header.to_string(),
+            "label: gpt
+label-id: 12121212-1212-1212-1212-121212121212
+device: disk.img
+unit: sectors
+first-lba: 34
+last-lba: 4062
+sector-size: 512"
+        );
+        let lines: Vec<String> =
+            disk.partitions.iter().map(|part| part.to_string()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "disk.img1 : start=        2048, size=        1024, type=AFAFAFAF-AFAF-AFAF-AFAF-AFAFAFAFAFAF, uuid=01010101-0101-0101-0101-010101010101, name=\"data\"",
+                "disk.img3 : start=          34, size=        2014, type=EFEFEFEF-EFEF-EFEF-EFEF-EFEFEFEFEFEF, uuid=02020202-0202-0202-0202-020202020202, name=\"EFI system\", attrs=\"RequiredPartition GUID:63\"",
+            ]
+        );
+        assert_eq!(
+            disk.partitions[0].part_type,
+            Some(PartitionType::Gpt(String::from(
+                "AFAFAFAF-AFAF-AFAF-AFAF-AFAFAFAFAFAF"
+            )))
+        );
+        assert_eq!(disk.validate(), vec![]);
+    }
+
+    #[test]
+    fn test_read_gpt_disk_round_trip() {
+        let path = std::env::temp_dir()
+            .join(format!("sfdisk-sort-rs-{}.img", std::process::id()));
+        std::fs::write(&path, image(512, &test_entries())).unwrap();
+        let disk = read_gpt_disk(&path);
+        std::fs::remove_file(&path).unwrap();
+        let disk = disk.unwrap();
+
+        // The dump printed for `--image` parses back as the same disk
+        let mut dump = format!("{}\n\n", disk.header);
+        for part in &disk.partitions {
+            dump.push_str(&format!("{}\n", part));
+        }
+        assert_eq!(parse_sfdisk_full_disk(dump).unwrap(), disk);
+    }
+
+    #[test]
+    fn test_guid() {
+        let bytes = [
+            0x28, 0x73, 0x2a, 0xc1, 0x1f, 0xf8, 0xd2, 0x11, 0xba, 0x4b, 0x00, 0xa0, 0xc9,
+            0x3e, 0xc9, 0x3b,
+        ];
+        assert_eq!(
+            super::guid_to_string(&bytes),
+            "C12A7328-F81F-11D2-BA4B-00A0C93EC93B"
+        );
+    }
+
+    #[test]
+    fn test_read_gpt_4k() {
+        let image = image(4096, &test_entries());
+        let gpt = read_gpt(&mut Cursor::new(image)).unwrap();
+
+        assert_eq!(gpt.header.sector_size, 4096);
+        assert_eq!(gpt.header.first_usable_lba, 6);
+        assert_eq!(gpt.entries.len(), 2);
+    }
+
+    #[test]
+    fn test_read_gpt_corrupt() {
+        let mut no_mbr = image(512, &test_entries());
+        no_mbr[510] = 0;
+        assert!(matches!(
+            read_gpt(&mut Cursor::new(no_mbr)),
+            Err(GptError::NoProtectiveMbr)
+        ));
+
+        let mut bad_header = image(512, &test_entries());
+        // First usable LBA
+        bad_header[512 + 40] = 35;
+        assert!(matches!(
+            read_gpt(&mut Cursor::new(bad_header)),
+            Err(GptError::HeaderCrc { .. })
+        ));
+
+        let mut bad_entries = image(512, &test_entries());
+        // Name of the first partition
+        bad_entries[1024 + 56] = b'D';
+        assert!(matches!(
+            read_gpt(&mut Cursor::new(bad_entries)),
+            Err(GptError::EntriesCrc { .. })
+        ));
+
+        let mut no_header = image(512, &test_entries());
+        no_header[512] = 0;
+        assert!(matches!(
+            read_gpt(&mut Cursor::new(no_header)),
+            Err(GptError::NoHeader)
+        ));
+    }
+
+    /// Recomputes both CRC32s of the primary GPT of a 512-byte sector `image`.
+    fn reseal(image: &mut [u8]) {
+        let entries_crc = crc32fast::hash(&image[1024..1024 + 128 * 128]);
+        image[512 + 88..512 + 92].copy_from_slice(&entries_crc.to_le_bytes());
+        let crc = header_crc(&image[512..512 + 92]);
+        image[512 + 16..512 + 20].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    #[test]
+    fn test_read_gpt_inconsistent() {
+        let patched = |offset: usize, value: u64| {
+            let mut image = image(512, &test_entries());
+            image[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+            reseal(&mut image);
+            read_gpt(&mut Cursor::new(image))
+        };
+
+        // Current LBA, backup LBA, last usable LBA and entries LBA of the header
+        for (offset, value) in [
+            (512 + 24, 2),
+            (512 + 32, TEST_SECTORS),
+            (512 + 48, TEST_SECTORS),
+            (512 + 72, u64::MAX / 256),
+            (512 + 72, 40),
+        ] {
+            assert!(
+                matches!(patched(offset, value), Err(GptError::InvalidHeader(_))),
+                "offset {} value {}",
+                offset,
+                value
+            );
+        }
+
+        // Last LBA of the first partition, before its first LBA 2048
+        assert!(matches!(
+            patched(1024 + 40, 2000),
+            Err(GptError::InvalidEntry {
+                number: 1,
+                first_lba: 2048,
+                last_lba: 2000
+            })
+        ));
+    }
+}
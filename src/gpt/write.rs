@@ -0,0 +1,452 @@
+use super::{
+    check_header, header_crc, parse_entry, parse_header, read_at, read_entries, read_gpt,
+    GptEntry, GptHeader,
+};
+use crate::disk::Disk;
+use crate::error::GptError;
+
+use std::fs::OpenOptions;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Offset of the header CRC32 field in the GPT header.
+const HEADER_CRC_OFFSET: usize = 16;
+/// Offset of the partition entries CRC32 field in the GPT header.
+const ENTRIES_CRC_OFFSET: usize = 88;
+
+/// Which part of the GPT a `ByteChange` is in.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[non_exhaustive]
+pub enum GptRegion {
+    /// Header in LBA 1
+    PrimaryHeader,
+    /// Entry of partition `slot + 1` in the primary entry array
+    PrimaryEntry(usize),
+    /// Header in the last LBA of the disk
+    BackupHeader,
+    /// Entry of partition `slot + 1` in the backup entry array
+    BackupEntry(usize),
+}
+
+impl GptRegion {
+    /// Reports whether the region is part of the backup GPT.
+    pub fn is_backup(&self) -> bool {
+        matches!(self, Self::BackupHeader | Self::BackupEntry(_))
+    }
+}
+
+impl std::fmt::Display for GptRegion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::PrimaryHeader => write!(f, "primary GPT header"),
+            Self::PrimaryEntry(slot) => write!(f, "primary GPT entry {}", slot + 1),
+            Self::BackupHeader => write!(f, "backup GPT header"),
+            Self::BackupEntry(slot) => write!(f, "backup GPT entry {}", slot + 1),
+        }
+    }
+}
+
+/// Bytes of the image that `write_gpt` overwrites, at byte `offset`. Header changes are
+/// the exact runs of changed bytes, while entry changes cover the whole entry.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[non_exhaustive]
+pub struct ByteChange {
+    /// Part of the GPT the bytes are in
+    pub region: GptRegion,
+    /// Byte offset in the image
+    pub offset: u64,
+    /// Bytes before the write
+    pub old: Vec<u8>,
+    /// Bytes after the write, as long as `old`
+    pub new: Vec<u8>,
+}
+
+/// Writes the partition order of `disk` into the GPT of the disk image or block device
+/// at `path`, or only returns what would change if `dry_run`, see `write_gpt`.
+pub fn write_gpt_disk(
+    path: impl AsRef<Path>,
+    disk: &Disk,
+    dry_run: bool,
+) -> Result<Vec<ByteChange>, GptError> {
+    let mut image = OpenOptions::new().read(true).write(!dry_run).open(path)?;
+    if dry_run {
+        return plan_gpt(&mut image, disk);
+    }
+
+    write_gpt_synced(&mut image, disk, |image| image.sync_data())
+}
+
+/// Returns the bytes `write_gpt` would change in `image`, in offset order, without
+/// writing anything.
+///
+/// Each partition of `disk` is matched to its GPT entry by uuid, and must have the same
+/// start and size. The entry array is rebuilt with each entry, unchanged, in the slot of
+/// the partition's designation, so only the order changes, and the CRC32s of both
+/// headers are recomputed.
+pub fn plan_gpt<R: Read + Seek>(
+    image: &mut R,
+    disk: &Disk,
+) -> Result<Vec<ByteChange>, GptError> {
+    let primary = read_gpt(image)?.header;
+    let new_entries = arrange_entries(&primary, &read_entries(image, &primary)?, disk)?;
+    let new_crc = crc32fast::hash(&new_entries);
+
+    let backup_sector = read_at(
+        image,
+        primary.offset(primary.backup_lba)?,
+        primary.sector_size as usize,
+    )?;
+    let backup = parse_header(&backup_sector, primary.sector_size)?;
+    check_header(&backup, primary.backup_lba, image.seek(SeekFrom::End(0))?)?;
+    if (backup.entry_count, backup.entry_size)
+        != (primary.entry_count, primary.entry_size)
+    {
+        return Err(GptError::InvalidHeader(String::from(
+            "the backup GPT header has another partition entry array than the primary one",
+        )));
+    }
+
+    let mut changes: Vec<ByteChange> = Vec::new();
+    for (header, header_region, entry_region) in [
+        (
+            &primary,
+            GptRegion::PrimaryHeader,
+            GptRegion::PrimaryEntry as fn(usize) -> GptRegion,
+        ),
+        (
+            &backup,
+            GptRegion::BackupHeader,
+            GptRegion::BackupEntry as fn(usize) -> GptRegion,
+        ),
+    ] {
+        let entries_offset = header.offset(header.entries_lba)?;
+        let old_entries = read_entries(image, header)?;
+        let entry_size = header.entry_size as usize;
+        for (slot, (old, new)) in old_entries
+            .chunks_exact(entry_size)
+            .zip(new_entries.chunks_exact(entry_size))
+            .enumerate()
+        {
+            if old != new {
+                changes.push(ByteChange {
+                    region: entry_region(slot),
+                    offset: entries_offset
+                        .checked_add((slot * entry_size) as u64)
+                        .ok_or_else(|| {
+                            GptError::InvalidHeader(String::from(
+                                "partition entries are past the largest byte offset",
+                            ))
+                        })?,
+                    old: old.to_vec(),
+                    new: new.to_vec(),
+                });
+            }
+        }
+
+        let header_offset = header.offset(header.current_lba)?;
+        let old_header = read_at(image, header_offset, header.header_size as usize)?;
+        let mut new_header = old_header.clone();
+        new_header[ENTRIES_CRC_OFFSET..ENTRIES_CRC_OFFSET + 4]
+            .copy_from_slice(&new_crc.to_le_bytes());
+        let crc = header_crc(&new_header);
+        new_header[HEADER_CRC_OFFSET..HEADER_CRC_OFFSET + 4]
+            .copy_from_slice(&crc.to_le_bytes());
+        changes.extend(changed_runs(
+            header_region,
+            header_offset,
+            &old_header,
+            &new_header,
+        ));
+    }
+    changes.sort_by_key(|change| change.offset);
+
+    Ok(changes)
+}
+
+/// Rewrites the GPT of `image` in the partition order of `disk`, see `plan_gpt`, and
+/// returns the bytes it changed.
+///
+/// The backup GPT is written and flushed before the primary one is touched, so if
+/// writing stops halfway, at least one of the two still has matching CRC32s.
+/// `write_gpt_disk` also syncs each copy to the device before going on.
+pub fn write_gpt<F: Read + Write + Seek>(
+    image: &mut F,
+    disk: &Disk,
+) -> Result<Vec<ByteChange>, GptError> {
+    write_gpt_synced(image, disk, |image| image.flush())
+}
+
+/// Writes the changes of `plan_gpt` to the backup GPT, then to the primary one, calling
+/// `sync` after each.
+fn write_gpt_synced<F: Read + Write + Seek>(
+    image: &mut F,
+    disk: &Disk,
+    sync: impl Fn(&mut F) -> io::Result<()>,
+) -> Result<Vec<ByteChange>, GptError> {
+    let changes = plan_gpt(image, disk)?;
+    for backup in [true, false] {
+        write_changes(image, &changes, backup)?;
+        sync(image)?;
+    }
+
+    Ok(changes)
+}
+
+/// Writes the `changes` to the backup GPT if `backup`, else to the primary one. The
+/// entries are written before the header, whose CRC32 covers them.
+fn write_changes<F: Write + Seek>(
+    image: &mut F,
+    changes: &[ByteChange],
+    backup: bool,
+) -> Result<(), GptError> {
+    let (headers, entries): (Vec<&ByteChange>, Vec<&ByteChange>) = changes
+        .iter()
+        .filter(|change| change.region.is_backup() == backup)
+        .partition(|change| {
+            matches!(
+                change.region,
+                GptRegion::PrimaryHeader | GptRegion::BackupHeader
+            )
+        });
+    for change in entries.into_iter().chain(headers) {
+        image.seek(SeekFrom::Start(change.offset))?;
+        image.write_all(&change.new)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the entry array of `header` with the entries of the partitions of `disk` in
+/// the slots of their designations.
+fn arrange_entries(
+    header: &GptHeader,
+    old_entries: &[u8],
+    disk: &Disk,
+) -> Result<Vec<u8>, GptError> {
+    let entry_size = header.entry_size as usize;
+    let old_slots: Vec<GptEntry> = old_entries
+        .chunks_exact(entry_size)
+        .enumerate()
+        .filter_map(|(slot, bytes)| parse_entry(slot, bytes))
+        .collect();
+
+    let mut new_entries = vec![0u8; old_entries.len()];
+    // Old and new slots already taken, so no entry is lost or written twice
+    let mut used: Vec<usize> = Vec::with_capacity(disk.partitions.len());
+    let mut filled: Vec<usize> = Vec::with_capacity(disk.partitions.len());
+    for part in &disk.partitions {
+        let old_slot = old_slots
+            .iter()
+            .find(|entry| {
+                !used.contains(&entry.slot)
+                    && part
+                        .uuid
+                        .as_deref()
+                        .is_some_and(|uuid| uuid.eq_ignore_ascii_case(&entry.unique_guid))
+                    && part.start_block == entry.first_lba
+                    && part.end_block() == Some(entry.last_lba)
+            })
+            .ok_or_else(|| GptError::NotInImage(part.name.clone()))?
+            .slot;
+
+        let new_slot = match part.designation.checked_sub(1) {
+            Some(new_slot) => new_slot,
+            None => return Err(GptError::ZeroDesignation(part.name.clone())),
+        };
+        if new_slot >= header.entry_count as usize {
+            return Err(GptError::TooFewEntries {
+                partition: part.name.clone(),
+                entry_count: header.entry_count,
+            });
+        }
+        if filled.contains(&new_slot) {
+            return Err(GptError::DesignationTaken {
+                partition: part.name.clone(),
+                designation: part.designation,
+            });
+        }
+        new_entries[new_slot * entry_size..(new_slot + 1) * entry_size].copy_from_slice(
+            &old_entries[old_slot * entry_size..(old_slot + 1) * entry_size],
+        );
+        used.push(old_slot);
+        filled.push(new_slot);
+    }
+
+    if let Some(missing) = old_slots.iter().find(|entry| !used.contains(&entry.slot)) {
+        return Err(GptError::MissingFromDisk(missing.unique_guid.clone()));
+    }
+
+    Ok(new_entries)
+}
+
+/// Returns the runs of bytes that differ between `old` and `new`, starting at `offset`.
+fn changed_runs(
+    region: GptRegion,
+    offset: u64,
+    old: &[u8],
+    new: &[u8],
+) -> Vec<ByteChange> {
+    let mut runs: Vec<ByteChange> = Vec::new();
+    let mut i = 0;
+    while i < old.len() {
+        if old[i] == new[i] {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        while i < old.len() && old[i] != new[i] {
+            i += 1;
+        }
+        runs.push(ByteChange {
+            region,
+            offset: offset + start as u64,
+            old: old[start..i].to_vec(),
+            new: new[start..i].to_vec(),
+        });
+    }
+
+    runs
+}
+
+#[cfg(test)]
+mod write_test {
+    use super::{plan_gpt, write_changes, write_gpt, GptRegion};
+    use crate::error::GptError;
+    use crate::gpt::gpt_test::{image, test_entries, TEST_SECTORS};
+    use crate::gpt::read_gpt;
+
+    use std::io::Cursor;
+
+    #[test]
+    fn test_write_gpt() {
+        let mut image = Cursor::new(image(512, &test_entries()));
+        let mut disk = read_gpt(&mut image).unwrap().to_disk("disk.img").unwrap();
+        disk.rearrange().unwrap();
+
+        let dry_run = image.get_ref().clone();
+        let planned = plan_gpt(&mut image, &disk).unwrap();
+        assert_eq!(image.get_ref(), &dry_run);
+
+        let changes = write_gpt(&mut image, &disk).unwrap();
+        assert_eq!(changes, planned);
+        let regions: Vec<GptRegion> =
+            changes.iter().map(|change| change.region).collect();
+        // The ESP in slot 3 moves to slot 1, and the data partition to slot 2
+        assert!(regions.contains(&GptRegion::PrimaryEntry(0)));
+        assert!(regions.contains(&GptRegion::PrimaryEntry(1)));
+        assert!(regions.contains(&GptRegion::PrimaryEntry(2)));
+        assert!(regions.contains(&GptRegion::PrimaryHeader));
+        assert!(regions.contains(&GptRegion::BackupEntry(2)));
+        assert!(regions.contains(&GptRegion::BackupHeader));
+        assert_eq!(changes[0].offset, 512 + 16);
+
+        // Both copies are valid, and hold the sorted table
+        let reread = read_gpt(&mut image).unwrap();
+        let names: Vec<&str> = reread.entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["EFI system", "data"]);
+        assert_eq!(reread.to_disk("disk.img").unwrap(), disk);
+
+        let backup_offset = (TEST_SECTORS - 1) * 512;
+        let backup_sector = super::read_at(&mut image, backup_offset, 512).unwrap();
+        let backup = super::parse_header(&backup_sector, 512).unwrap();
+        assert_eq!(backup.entries_crc32, reread.header.entries_crc32);
+        assert_eq!(
+            super::read_entries(&mut image, &backup).unwrap(),
+            super::read_entries(&mut image, &reread.header).unwrap()
+        );
+
+        // Writing the sorted table again changes nothing
+        assert_eq!(plan_gpt(&mut image, &disk).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_write_gpt_interrupted() {
+        let mut image = Cursor::new(image(512, &test_entries()));
+        let old = read_gpt(&mut image).unwrap();
+        let mut disk = old.to_disk("disk.img").unwrap();
+        disk.rearrange().unwrap();
+
+        // Stop after the backup phase
+        let changes = plan_gpt(&mut image, &disk).unwrap();
+        write_changes(&mut image, &changes, true).unwrap();
+
+        // The primary GPT still holds the old table, and the backup the sorted one
+        assert_eq!(read_gpt(&mut image).unwrap(), old);
+        let backup_offset = (TEST_SECTORS - 1) * 512;
+        let backup_sector = super::read_at(&mut image, backup_offset, 512).unwrap();
+        let backup = super::parse_header(&backup_sector, 512).unwrap();
+        let backup_entries = super::read_entries(&mut image, &backup).unwrap();
+        let names: Vec<String> = backup_entries
+            .chunks_exact(128)
+            .enumerate()
+            .filter_map(|(slot, bytes)| super::parse_entry(slot, bytes))
+            .map(|entry| entry.name)
+            .collect();
+        assert_eq!(names, vec!["EFI system", "data"]);
+
+        // Finishing the write completes the primary GPT too
+        write_changes(&mut image, &changes, false).unwrap();
+        assert_eq!(
+            read_gpt(&mut image).unwrap().to_disk("disk.img").unwrap(),
+            disk
+        );
+    }
+
+    #[test]
+    fn test_write_gpt_mismatch() {
+        let mut image = Cursor::new(image(512, &test_entries()));
+        let disk = read_gpt(&mut image).unwrap().to_disk("disk.img").unwrap();
+
+        let mut moved = disk.clone();
+        moved.partitions[0].start_block = 4096;
+        assert!(matches!(
+            plan_gpt(&mut image, &moved),
+            Err(GptError::NotInImage(name)) if name == "disk.img1"
+        ));
+
+        let mut dropped = disk.clone();
+        dropped.partitions.pop();
+        assert!(matches!(
+            plan_gpt(&mut image, &dropped),
+            Err(GptError::MissingFromDisk(_))
+        ));
+
+        let mut doubled = disk.clone();
+        doubled.partitions[1].designation = 1;
+        assert!(matches!(
+            plan_gpt(&mut image, &doubled),
+            Err(GptError::DesignationTaken { designation: 1, .. })
+        ));
+
+        let mut zero = disk.clone();
+        zero.partitions[0].designation = 0;
+        assert!(matches!(
+            plan_gpt(&mut image, &zero),
+            Err(GptError::ZeroDesignation(name)) if name == "disk.img1"
+        ));
+
+        let mut renumbered = disk.clone();
+        renumbered.partitions[0].designation = 129;
+        assert!(matches!(
+            plan_gpt(&mut image, &renumbered),
+            Err(GptError::TooFewEntries {
+                entry_count: 128,
+                ..
+            })
+        ));
+
+        // A backup header that says it is elsewhere
+        let backup_offset = ((TEST_SECTORS - 1) * 512) as usize;
+        let misplaced = image.get_mut();
+        misplaced[backup_offset + 24..backup_offset + 32]
+            .copy_from_slice(&(TEST_SECTORS - 2).to_le_bytes());
+        let crc = super::header_crc(&misplaced[backup_offset..backup_offset + 92]);
+        misplaced[backup_offset + 16..backup_offset + 20]
+            .copy_from_slice(&crc.to_le_bytes());
+        assert!(matches!(
+            plan_gpt(&mut image, &disk),
+            Err(GptError::InvalidHeader(_))
+        ));
+    }
+}
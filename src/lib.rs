@@ -0,0 +1,55 @@
+//! Parses `sfdisk -d` and `sfdisk -J` dumps, and sorts their partitions by start block.
+//!
+//! ```no_run
+//! let dump = std::fs::read_to_string("sda.dump").unwrap();
+//! let mut disk = sfdisk_sort_rs::parse_sfdisk_full_disk(dump).unwrap();
+//!
+//! assert!(disk.validate().is_empty());
+//! for renaming in disk.rearrange().unwrap() {
+//!     println!("{} -> {}", renaming.old_name, renaming.new_name);
+//! }
+//! for partition in disk.partitions() {
+//!     println!("{}", partition);
+//! }
+//! ```
+//!
+//! The items re-exported here, and the `report`, `diff` and `fstab` modules that format
+//! them for the command line, are the public API.
+//!
+//! `Disk::rearrange_by` takes a `SortKey` to order partitions by something other than
+//! their start block, e.g. `SortKey::TypeGroups` to make the ESP partition 1.
+//! `Disk::rearrange_with` also takes `Pin`s, partitions that keep a fixed number, and a
+//! `Numbering` to start elsewhere than 1, leave gaps or keep the numbers already in use.
+//!
+//! `parse_sfdisk_partition_line` parses a single partition line on its own, e.g. one
+//! edited by hand, and `Disk::new` puts such partitions together with a `DiskHeader`.
+//!
+//! `read_gpt_disk` reads the same `Disk` from a disk image file instead of a dump, and
+//! `write_gpt_disk` writes its sorted partition order back into the image.
+
+#![warn(missing_docs)]
+
+pub mod diff;
+mod disk;
+mod error;
+pub mod fstab;
+mod gpt;
+mod linux;
+mod partition;
+pub mod report;
+
+pub use disk::align::{Alignment, Misalignment};
+pub use disk::free::FreeSpace;
+pub use disk::header::{DiskHeader, Label};
+pub use disk::json::{is_sfdisk_json, parse_sfdisk_json_disk, to_sfdisk_json};
+pub use disk::numbering::Numbering;
+pub use disk::pin::{Pin, PinTarget};
+pub use disk::sort::{Comparator, SortKey};
+pub use disk::validate::Diagnostic;
+pub use disk::{parse_sfdisk_disks, parse_sfdisk_full_disk, Arrangement, Disk};
+pub use error::{render, GptError, LayoutError, NameSpans, ParseError, Span, SysfsError};
+pub use gpt::write::{plan_gpt, write_gpt, write_gpt_disk, ByteChange, GptRegion};
+pub use gpt::{read_gpt, read_gpt_disk, Gpt, GptEntry, GptHeader};
+pub use linux::block::{LinuxBlockDevice, Sysfs, SysfsPartition};
+pub use partition::parse::{is_sfdisk_partition_line, parse_sfdisk_partition_line};
+pub use partition::{Partition, PartitionType, Renaming};
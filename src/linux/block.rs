@@ -1,15 +1,21 @@
-use crate::error::{RegexError, RegexCapturesError};
+use crate::error::SysfsError;
 
 use lazy_static::lazy_static;
 use regex::Regex;
-use anyhow::{Error, Result, Context};
 
 use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
 
-/// Represents my commonly used block device names.
-#[derive(Clone, Copy, Hash, PartialEq, Eq)]
+/// Represents my commonly used block device names. Partition names do not depend on it,
+/// see `linux::naming`, so other devices (e.g. `loop0` or `md127`) are just unclassified.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Default, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum LinuxBlockDevice {
     /// SCSI, ATA, and SATA
+    #[default]
     SCSI,
     /// Virtual devices, e.g. '/dev/vdX' and '/dev/xvd'
     VIRT,
@@ -19,15 +25,15 @@ pub enum LinuxBlockDevice {
     NVME,
 }
 
-const SCSI_REGEX: &str = r"sd[a-z]";
-const VIRT_REGEX: &str = r"vd[a-z]";
-const NVME_REGEX: &str = r"nvme\d+[n]\d+";
-const MMBCLK_REGEX: &str = r"mmcblk\d+";
+const SCSI_REGEX: &str = r"^/dev/sd[a-z]";
+const VIRT_REGEX: &str = r"^/dev/x?vd[a-z]";
+const NVME_REGEX: &str = r"^/dev/nvme\d+[n]\d+";
+const MMBCLK_REGEX: &str = r"^/dev/mmcblk\d+";
 
-const SCSI_PART_REGEX: &str = r"(?P<prefix>/dev/sd[a-z])(?P<part_num>\d+)";
-const VIRT_PART_REGEX: &str = r"(?P<prefix>/dev/vd[a-z])(?P<part_num>\d+)";
-const NVME_PART_REGEX: &str = r"(?P<prefix>/dev/nvme\d+[n]\d+[p])(?P<part_num>\d+)";
-const MMBCLK_PART_REGEX: &str = r"(?P<prefix>/dev/mmcblk\d+[p])(?P<part_num>\d+)";
+const SCSI_PART_REGEX: &str = r"^(?P<prefix>/dev/sd[a-z]+)(?P<part_num>\d+)$";
+const VIRT_PART_REGEX: &str = r"^(?P<prefix>/dev/x?vd[a-z]+)(?P<part_num>\d+)$";
+const NVME_PART_REGEX: &str = r"^(?P<prefix>/dev/nvme\d+[n]\d+[p])(?P<part_num>\d+)$";
+const MMBCLK_PART_REGEX: &str = r"^(?P<prefix>/dev/mmcblk\d+[p])(?P<part_num>\d+)$";
 
 lazy_static! {
     pub static ref BLK_REGEX: HashMap<LinuxBlockDevice, Regex> = HashMap::from([
@@ -37,9 +43,9 @@ lazy_static! {
         (LinuxBlockDevice::NVME, Regex::new(NVME_REGEX).unwrap()),
     ]);
 
-    /// These Regexes are used during rearranging/redesignation
-    /// by extracting all the text in the device name before the partition number,
-    /// in `nvme` and `mmcblk` cases, the `prefix` also includes the 'p'.
+    /// Whole partition names of the classified devices, split into the text before the
+    /// partition number and the number. In `nvme` and `mmcblk` cases, the `prefix` also
+    /// includes the 'p'. Renaming does not use them, see `linux::naming`.
     pub static ref BLK_PART_REGEX: HashMap<LinuxBlockDevice, Regex> = HashMap::from([
         (LinuxBlockDevice::SCSI, Regex::new(SCSI_PART_REGEX).unwrap()),
         (LinuxBlockDevice::VIRT, Regex::new(VIRT_PART_REGEX).unwrap()),
@@ -48,6 +54,8 @@ lazy_static! {
     ]);
 }
 
+/// Classifies the disk or partition `device_name` by its kernel name under `/dev`, e.g.
+/// `SCSI` for `/dev/sda` or `/dev/sda1`, but nothing for `/dev/mapper/vdisk`.
 pub fn linux_blk_name(device_name: &str) -> Option<LinuxBlockDevice> {
     for (disk_type, re) in BLK_REGEX
         .clone()
@@ -62,112 +70,307 @@ pub fn linux_blk_name(device_name: &str) -> Option<LinuxBlockDevice> {
     None
 }
 
-/// This function extracts the partition prefix and the partition number.
-/// e.g. `/dev/sda1000` will be extracted as tuple `("sda", "1")`,
-/// while `/dev/mmcblk10p2` will be extracted as `("mmcblk10", "2")`.
+/// Extracts the partition prefix and the partition number of `part_name` if it is a
+/// partition of a `blk_dev` disk, e.g. `("/dev/sda", "1000")` for `/dev/sda1000`, and
+/// `("/dev/mmcblk10p", "2")` for `/dev/mmcblk10p2`.
 pub fn linux_part_prefix_and_part_num(
     blk_dev: LinuxBlockDevice,
     part_name: &str,
-) -> Result<(&str, &str)> {
-    let re = BLK_PART_REGEX.get(&blk_dev).unwrap();
-    let caps = re.captures(part_name);
-    if caps.is_none() {
-        return Err(Error::from(RegexError)).with_context(|| {
-            format!(
-                "failed to match {:?} prefix and partition number for {}",
-                blk_dev, part_name
-            )
-        });
+) -> Option<(&str, &str)> {
+    let caps = BLK_PART_REGEX.get(&blk_dev)?.captures(part_name)?;
+
+    Some((
+        caps.name("prefix")?.as_str(),
+        caps.name("part_num")?.as_str(),
+    ))
+}
+
+/// Returns the kind of disk `part_name` is a partition of, if it is named like the
+/// partition of a classified disk, e.g. `SCSI` for `/dev/sda1`.
+pub fn linux_part_blk_name(part_name: &str) -> Option<LinuxBlockDevice> {
+    BLK_PART_REGEX
+        .keys()
+        .copied()
+        .find(|disk_type| linux_part_prefix_and_part_num(*disk_type, part_name).is_some())
+}
+
+/// Where the running kernel lists its block devices, one directory per disk holding
+/// one directory per partition.
+pub const SYSFS_BLOCK_ROOT: &str = "/sys/class/block";
+
+/// Where the running kernel's device nodes and udev links are.
+pub const DEV_ROOT: &str = "/dev";
+
+/// Most links followed when resolving a device name, like the kernel's limit.
+const MAX_LINKS: usize = 40;
+
+/// Size of the sectors sysfs counts `start` and `size` in, whatever the disk sector size.
+pub const SYSFS_SECTOR_SIZE: u64 = 512;
+
+/// A partition as the running kernel sees it.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[non_exhaustive]
+pub struct SysfsPartition {
+    /// Kernel name, e.g. `sda1` or `nvme0n1p2`
+    pub kernel_name: String,
+    /// Partition number, from the `partition` file
+    pub designation: usize,
+    /// First sector, in 512-byte sectors
+    pub start: u64,
+    /// Size, in 512-byte sectors
+    pub size: u64,
+}
+
+/// Reads disks and partitions from a sysfs block directory, `/sys/class/block` unless
+/// `root` points elsewhere, e.g. at a copy of it or a fake tree in tests.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[non_exhaustive]
+pub struct Sysfs {
+    /// Directory with one directory per block device, like `/sys/class/block`
+    pub root: PathBuf,
+    /// Directory standing for `/dev` when resolving links such as
+    /// `/dev/disk/by-id/...`, `/dev` for the running kernel. Without it, disks are
+    /// looked up by the file name of their device.
+    pub dev_root: Option<PathBuf>,
+}
+
+impl Default for Sysfs {
+    fn default() -> Self {
+        Self::new(SYSFS_BLOCK_ROOT).with_dev_root(DEV_ROOT)
+    }
+}
+
+impl Sysfs {
+    /// Reads the sysfs block directory `root`, looking disks up by file name.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Sysfs {
+            root: root.into(),
+            dev_root: None,
+        }
+    }
+
+    /// Resolves the links of device names in `dev_root`, e.g. a copy of `/dev` taken
+    /// with the sysfs tree.
+    pub fn with_dev_root(mut self, dev_root: impl Into<PathBuf>) -> Self {
+        self.dev_root = Some(dev_root.into());
+        self
+    }
+
+    /// Returns the partitions the kernel has for the disk `disk_name`, by partition
+    /// number. Links such as `/dev/disk/by-id/...` or `/dev/mapper/...` are resolved to
+    /// the kernel name of the disk if they exist in `dev_root`.
+    pub fn partitions(&self, disk_name: &str) -> Result<Vec<SysfsPartition>, SysfsError> {
+        let disk_dir = self.root.join(self.kernel_name(disk_name));
+        if !disk_dir.is_dir() {
+            return Err(SysfsError::NoDevice {
+                device: String::from(disk_name),
+                path: self.root.clone(),
+            });
+        }
+
+        let entries = fs::read_dir(&disk_dir).map_err(|source| SysfsError::Read {
+            path: disk_dir.clone(),
+            source,
+        })?;
+        let mut partitions: Vec<SysfsPartition> = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(|source| SysfsError::Read {
+                path: disk_dir.clone(),
+                source,
+            })?;
+            let part_dir = entry.path();
+            // Other entries are attributes or directories like `queue` and `holders`
+            if !part_dir.join("partition").is_file() {
+                continue;
+            }
+
+            partitions.push(SysfsPartition {
+                kernel_name: entry.file_name().to_string_lossy().into_owned(),
+                designation: read_number(&part_dir.join("partition"))? as usize,
+                start: read_number(&part_dir.join("start"))?,
+                size: read_number(&part_dir.join("size"))?,
+            });
+        }
+        partitions.sort_by_key(|part| part.designation);
+
+        Ok(partitions)
     }
 
-    let caps = caps.unwrap();
-    let prefix = caps.name("prefix");
-    if prefix.is_none() {
-        return Err(Error::from(RegexCapturesError)).with_context(|| {
-            format!(
-                "missing prefix for {:?} (partition name {})",
-                blk_dev, part_name
-            )
-        });
+    /// Returns the name of the disk in sysfs, the file name of the device node after
+    /// following links in `dev_root`, e.g. `dm-0` for `/dev/mapper/vg-data`.
+    fn kernel_name(&self, disk_name: &str) -> String {
+        let resolved = self
+            .dev_root
+            .as_deref()
+            .zip(Path::new(disk_name).strip_prefix(DEV_ROOT).ok())
+            .and_then(|(dev_root, name)| resolve_links(dev_root, name))
+            .unwrap_or_else(|| PathBuf::from(disk_name));
+        match resolved.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => String::from(disk_name),
+        }
     }
-    let part_num = caps.name("part_num");
-    if part_num.is_none() {
-        return Err(Error::from(RegexCapturesError)).with_context(|| {
-            format!(
-                "missing partition number for {:?} (partition name {})",
-                blk_dev, part_name
-            )
-        });
+}
+
+/// Follows the links of `name`, a path relative to `dev_root`, and returns the path
+/// relative to `dev_root` it ends at. Returns None if a link leaves `dev_root`, or there
+/// are too many links.
+fn resolve_links(dev_root: &Path, name: &Path) -> Option<PathBuf> {
+    let mut name = name.to_path_buf();
+    for _ in 0..MAX_LINKS {
+        let target = match fs::read_link(dev_root.join(&name)) {
+            Ok(target) => target,
+            Err(_) => return Some(name),
+        };
+        let joined = match target.strip_prefix(DEV_ROOT) {
+            Ok(target) => target.to_path_buf(),
+            Err(_) if target.is_absolute() => return None,
+            Err(_) => name.parent().unwrap_or(Path::new("")).join(target),
+        };
+
+        // Resolve `..` without leaving `dev_root`
+        name = PathBuf::new();
+        for component in joined.components() {
+            if component == Component::ParentDir {
+                if !name.pop() {
+                    return None;
+                }
+            } else if let Component::Normal(part) = component {
+                name.push(part);
+            }
+        }
     }
 
-    Ok((prefix.unwrap().as_str(), part_num.unwrap().as_str()))
+    None
+}
+
+/// Reads a sysfs attribute holding one number, e.g. `start`.
+fn read_number(path: &Path) -> Result<u64, SysfsError> {
+    let text = fs::read_to_string(path).map_err(|source| SysfsError::Read {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    text.trim()
+        .parse::<u64>()
+        .map_err(|_| SysfsError::InvalidValue {
+            path: path.to_path_buf(),
+            value: String::from(text.trim()),
+        })
 }
 
 impl core::fmt::Debug for LinuxBlockDevice {
     fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
         match self {
             self::LinuxBlockDevice::SCSI => {
-                write!(f, "{}", "SCSI")
+                write!(f, "SCSI")
             }
             self::LinuxBlockDevice::VIRT => {
-                write!(f, "{}", "VIRT")
+                write!(f, "VIRT")
             }
             self::LinuxBlockDevice::MMCBLK => {
-                write!(f, "{}", "MMCBLK")
+                write!(f, "MMCBLK")
             }
             self::LinuxBlockDevice::NVME => {
-                write!(f, "{}", "NVME")
+                write!(f, "NVME")
             }
         }
     }
 }
 
-impl Default for LinuxBlockDevice {
-    fn default() -> Self {
-        Self::SCSI
-    }
-}
-
 #[cfg(test)]
 mod disk_tests {
-    use super::{linux_blk_name, linux_part_prefix_and_part_num, LinuxBlockDevice as ns};
+    use super::{
+        linux_blk_name, linux_part_blk_name, linux_part_prefix_and_part_num,
+        LinuxBlockDevice as ns, Sysfs, SysfsPartition,
+    };
     use std::collections::HashMap;
+    use std::path::PathBuf;
 
     #[test]
     fn test_diskname() {
-        let sda1000 = "/dev/sda1000";
-        let sdc1 = "/dev/sdc1";
-        let vda1 = "/dev/vda1";
-        let mmcblk10p20 = "/dev/mmcblk10p20";
-        let nvme0n1p1 = "/dev/nvme0n1p1";
-
         let expected_linux_blk_name = HashMap::from([
-            (sda1000, Some(ns::SCSI)),
-            (sdc1, Some(ns::SCSI)),
-            (nvme0n1p1, Some(ns::NVME)),
-            (mmcblk10p20, Some(ns::MMCBLK)),
-            (vda1, Some(ns::VIRT)),
+            ("/dev/sda1000", Some(ns::SCSI)),
+            ("/dev/sdc1", Some(ns::SCSI)),
+            ("/dev/nvme0n1p1", Some(ns::NVME)),
+            ("/dev/mmcblk10p20", Some(ns::MMCBLK)),
+            ("/dev/vda1", Some(ns::VIRT)),
+            ("/dev/xvdb", Some(ns::VIRT)),
+            ("/dev/loop0", None),
             ("/dev/nvme1", None),
             ("/dev/sd1", None),
+            ("/dev/mapper/vdisk2", None),
+            ("/dev/disk/by-id/usb-Msdata_XYZ", None),
         ]);
 
         for device in expected_linux_blk_name.iter() {
-            let result_ns = linux_blk_name(*device.0);
+            let result_ns = linux_blk_name(device.0);
             assert_eq!(result_ns, *expected_linux_blk_name.get(*device.0).unwrap());
         }
 
         let expected_captures: HashMap<(&str, ns), (&str, &str)> = HashMap::from([
-            ((sda1000, ns::SCSI), ("/dev/sda", "1000")),
-            ((sdc1, ns::SCSI), ("/dev/sdc", "1")),
-            ((vda1, ns::VIRT), ("/dev/vda", "1")),
-            ((mmcblk10p20, ns::MMCBLK), ("/dev/mmcblk10p", "20")),
-            ((nvme0n1p1, ns::NVME), ("/dev/nvme0n1p", "1")),
+            (("/dev/sda1000", ns::SCSI), ("/dev/sda", "1000")),
+            (("/dev/sdc1", ns::SCSI), ("/dev/sdc", "1")),
+            (("/dev/vda1", ns::VIRT), ("/dev/vda", "1")),
+            (("/dev/xvdb2", ns::VIRT), ("/dev/xvdb", "2")),
+            (("/dev/mmcblk10p20", ns::MMCBLK), ("/dev/mmcblk10p", "20")),
+            (("/dev/nvme0n1p1", ns::NVME), ("/dev/nvme0n1p", "1")),
         ]);
 
         for (test_tuple, expected_tuple) in expected_captures.iter() {
             let result_tuple =
                 linux_part_prefix_and_part_num(test_tuple.1, test_tuple.0).unwrap();
             assert_eq!(result_tuple, *expected_tuple);
+            assert_eq!(linux_part_blk_name(test_tuple.0), Some(test_tuple.1));
         }
+
+        assert_eq!(
+            linux_part_prefix_and_part_num(ns::NVME, "/dev/nvme0n1"),
+            None
+        );
+        assert_eq!(linux_part_blk_name("/dev/nvme0n1"), None);
+        assert_eq!(linux_part_blk_name("/dev/loop0p1"), None);
+    }
+
+    #[test]
+    fn test_sysfs_partitions() {
+        let sysfs = Sysfs::new("./assets/sysfs_block");
+        let parts = sysfs.partitions("/dev/sda").unwrap();
+
+        // Only the partition directories, not `queue`
+        let names: Vec<&str> =
+            parts.iter().map(|part| part.kernel_name.as_str()).collect();
+        assert_eq!(names, vec!["sda1", "sda2", "sda3", "sda5"]);
+        assert_eq!(
+            parts[1],
+            SysfsPartition {
+                kernel_name: String::from("sda2"),
+                designation: 2,
+                start: 411648,
+                size: 67108864,
+            }
+        );
+
+        assert!(sysfs.partitions("/dev/nvme0n1").is_err());
+        assert_eq!(Sysfs::default().root, PathBuf::from("/sys/class/block"));
+        assert_eq!(Sysfs::default().dev_root, Some(PathBuf::from("/dev")));
+    }
+
+    #[test]
+    fn test_sysfs_links() {
+        let sysfs = Sysfs::new("./assets/sysfs_block");
+        // Without a dev root, links are not resolved, not even against the host's /dev
+        assert_eq!(sysfs.kernel_name("/dev/disk/by-id/ata-XYZ"), "ata-XYZ");
+
+        let sysfs = sysfs.with_dev_root("./assets/dev");
+        assert_eq!(sysfs.kernel_name("/dev/disk/by-id/ata-XYZ"), "sda");
+        assert_eq!(sysfs.kernel_name("/dev/sda"), "sda");
+        assert_eq!(
+            sysfs.partitions("/dev/disk/by-id/ata-XYZ").unwrap().len(),
+            4
+        );
+        // A link out of the dev root is not followed
+        assert_eq!(sysfs.kernel_name("/dev/disk/by-id/escape"), "escape");
+        assert_eq!(sysfs.kernel_name("disk.img"), "disk.img");
     }
 }
@@ -1 +1,2 @@
-pub mod block;
+pub(crate) mod block;
+pub(crate) mod naming;
@@ -0,0 +1,202 @@
+use super::block;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+/// Where udev links the disks by id, path, UUID and so on, e.g. `/dev/disk/by-id/`.
+const UDEV_LINK_DIR: &str = "/dev/disk/by-";
+/// What udev puts between its disk link names and the partition number.
+const UDEV_PART_SEPARATOR: &str = "-part";
+/// Kernel names of whole disks whose drivers number them, e.g. `/dev/loop0`, so that
+/// their partitions are named with a `p`, e.g. `/dev/loop0p1`.
+const NUMBERED_DISK_PATTERN: &str =
+    r"^/dev/(?:loop|md|nbd|zd|pmem|rbd|ram|dm-|nvme\d+n|mmcblk)\d+$";
+
+lazy_static! {
+    static ref NUMBERED_DISK_REGEX: Regex = Regex::new(NUMBERED_DISK_PATTERN).unwrap();
+}
+
+/// Reports whether `disk_name` can name a disk with partitions, e.g. `/dev/sda`,
+/// `/dev/loop0` or `/dev/mapper/vg-data`. Rejected are names with an empty last path
+/// component, e.g. `/dev/sda/`, and names of partitions: udev `-partN` links, and the
+/// partitions of the disks `block::linux_blk_name` classifies, e.g. `/dev/sda1` or
+/// `/dev/nvme0n1p2`. Partitions of other disks, e.g. `/dev/loop0p1`, look like disks.
+pub fn is_disk_name(disk_name: &str) -> bool {
+    let base = match disk_name.rsplit_once('/') {
+        Some((_, base)) => base,
+        None => disk_name,
+    };
+    if base.is_empty() || block::linux_part_blk_name(disk_name).is_some() {
+        return false;
+    }
+
+    match disk_name.rsplit_once(UDEV_PART_SEPARATOR) {
+        Some((link, part_num)) if link.starts_with(UDEV_LINK_DIR) => {
+            part_num.is_empty() || !part_num.chars().all(|c| c.is_ascii_digit())
+        }
+        _ => true,
+    }
+}
+
+/// Returns what the partition names of disk `disk_name` start with, before the partition
+/// number. Like the kernel, a `p` is appended when the disk name ends in a digit, e.g.
+/// `/dev/sda` for `/dev/sda`, but `/dev/nvme0n1p` for `/dev/nvme0n1` and `/dev/loop0p`
+/// for `/dev/loop0`. The udev links under `/dev/disk/by-*` use a `-part` suffix instead,
+/// e.g. `/dev/disk/by-id/ata-XYZ-part` for `/dev/disk/by-id/ata-XYZ`.
+pub fn part_prefix(disk_name: &str) -> String {
+    if disk_name.starts_with(UDEV_LINK_DIR) {
+        return format!("{}{}", disk_name, UDEV_PART_SEPARATOR);
+    }
+    if disk_name.ends_with(|c: char| c.is_ascii_digit()) {
+        return format!("{}p", disk_name);
+    }
+
+    String::from(disk_name)
+}
+
+/// Returns the name of partition `designation` of disk `disk_name`, e.g. `/dev/md127p2`.
+pub fn part_name(disk_name: &str, designation: usize) -> String {
+    format!("{}{}", part_prefix(disk_name), designation)
+}
+
+/// Returns the partition number in `part_name` if it is a partition of disk `disk_name`,
+/// e.g. `"10"` for `/dev/sda10` of `/dev/sda`, but nothing for `/dev/sdb1`. Whole disks
+/// the kernel numbers, e.g. `/dev/loop0` or `/dev/md127`, are not partitions of
+/// `/dev/loop` or `/dev/md`.
+pub fn part_num<'a>(disk_name: &str, part_name: &'a str) -> Option<&'a str> {
+    if NUMBERED_DISK_REGEX.is_match(part_name) {
+        return None;
+    }
+
+    let part_num = part_name.strip_prefix(&part_prefix(disk_name))?;
+    if part_num.is_empty() || !part_num.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    Some(part_num)
+}
+
+/// Returns the name of the disk `part_name` is a partition of, the reverse of
+/// `part_name`, e.g. `/dev/nvme0n1` for `/dev/nvme0n1p2`. Disks whose kernel names end
+/// in a number, e.g. `/dev/nvme0n1` or `/dev/loop0`, are not partitions, see `part_num`.
+pub fn disk_name(part_name: &str) -> Option<&str> {
+    let prefix = part_name.trim_end_matches(|c: char| c.is_ascii_digit());
+    let disk_name = match prefix.strip_suffix(UDEV_PART_SEPARATOR) {
+        Some(link) if link.starts_with(UDEV_LINK_DIR) => link,
+        _ => match prefix.strip_suffix('p') {
+            Some(disk_name) if disk_name.ends_with(|c: char| c.is_ascii_digit()) => {
+                disk_name
+            }
+            _ => prefix,
+        },
+    };
+    is_part_of(disk_name, part_name).then_some(disk_name)
+}
+
+/// Reports whether `part_name` is a partition of the disk `disk_name`,
+/// e.g. `/dev/sda1` of `/dev/sda`, but not `/dev/sdb1`.
+pub fn is_part_of(disk_name: &str, part_name: &str) -> bool {
+    part_num(disk_name, part_name).is_some()
+}
+
+#[cfg(test)]
+mod naming_test {
+    use super::{disk_name, is_disk_name, is_part_of, part_name, part_num};
+
+    #[test]
+    fn test_part_name() {
+        let expected = [
+            ("/dev/sda", "/dev/sda1"),
+            ("/dev/vda", "/dev/vda1"),
+            ("/dev/xvda", "/dev/xvda1"),
+            ("/dev/hda", "/dev/hda1"),
+            ("/dev/nvme0n1", "/dev/nvme0n1p1"),
+            ("/dev/mmcblk0", "/dev/mmcblk0p1"),
+            ("/dev/loop0", "/dev/loop0p1"),
+            ("/dev/nbd0", "/dev/nbd0p1"),
+            ("/dev/md127", "/dev/md127p1"),
+            ("/dev/pmem0", "/dev/pmem0p1"),
+            ("/dev/zd16", "/dev/zd16p1"),
+            ("/dev/dm-0", "/dev/dm-0p1"),
+            ("/dev/mapper/vg-data", "/dev/mapper/vg-data1"),
+            ("/dev/disk/by-id/ata-XYZ", "/dev/disk/by-id/ata-XYZ-part1"),
+            (
+                "/dev/disk/by-path/pci-0000:00:1f.2-ata-1",
+                "/dev/disk/by-path/pci-0000:00:1f.2-ata-1-part1",
+            ),
+        ];
+
+        for (disk_name, expected_name) in expected {
+            assert_eq!(part_name(disk_name, 1), expected_name);
+            assert!(is_part_of(disk_name, expected_name));
+        }
+    }
+
+    #[test]
+    fn test_is_part_of() {
+        assert_eq!(part_num("/dev/sda", "/dev/sda10"), Some("10"));
+        assert_eq!(part_num("/dev/md127", "/dev/md127p3"), Some("3"));
+        assert!(!is_part_of("/dev/sda", "/dev/sdb1"));
+        assert!(!is_part_of("/dev/sda", "/dev/sda"));
+        assert!(!is_part_of("/dev/loop0", "/dev/loop01"));
+        assert!(!is_part_of("/dev/nvme0n1", "/dev/nvme0n12p1"));
+        assert!(!is_part_of("/dev/loop", "/dev/loop0"));
+        assert!(!is_part_of("/dev/md", "/dev/md127"));
+        assert!(!is_part_of("/dev/nbd", "/dev/nbd0"));
+        assert_eq!(
+            part_num(
+                "/dev/disk/by-id/wwn-0x5000c500",
+                "/dev/disk/by-id/wwn-0x5000c500-part12"
+            ),
+            Some("12")
+        );
+        assert!(!is_part_of(
+            "/dev/disk/by-id/wwn-0x5000c500",
+            "/dev/disk/by-id/wwn-0x5000c500p1"
+        ));
+
+        assert!(is_disk_name("/dev/loop0"));
+        assert!(is_disk_name("/dev/md127"));
+        assert!(is_disk_name("/dev/nbd0"));
+        assert!(is_disk_name("/dev/foo"));
+        assert!(is_disk_name("/dev/disk/by-id/nvme-XYZ-partition"));
+        assert!(!is_disk_name("/dev/sda/"));
+        assert!(!is_disk_name("/dev/sda1"));
+        assert!(!is_disk_name("/dev/nvme0n1p2"));
+        assert!(!is_disk_name("/dev/disk/by-id/ata-XYZ-part1"));
+    }
+
+    #[test]
+    fn test_disk_name() {
+        let expected = [
+            ("/dev/sda10", Some("/dev/sda")),
+            ("/dev/nvme0n1p2", Some("/dev/nvme0n1")),
+            ("/dev/loop0p1", Some("/dev/loop0")),
+            ("/dev/mapper/vg-data1", Some("/dev/mapper/vg-data")),
+            (
+                "/dev/disk/by-id/ata-XYZ-part3",
+                Some("/dev/disk/by-id/ata-XYZ"),
+            ),
+            ("disk.img1", Some("disk.img")),
+            // Names merely containing a classified disk name
+            ("/dev/mapper/vdisk1", Some("/dev/mapper/vdisk")),
+            (
+                "/dev/disk/by-id/usb-Msdata_XYZ-part2",
+                Some("/dev/disk/by-id/usb-Msdata_XYZ"),
+            ),
+            ("/dev/nvme0n1", None),
+            ("/dev/mmcblk0", None),
+            // Whole disks numbered by their drivers
+            ("/dev/loop0", None),
+            ("/dev/md127", None),
+            ("/dev/nbd0", None),
+            ("/dev/md127p3", Some("/dev/md127")),
+            ("/dev/sda", None),
+            ("/dev/disk/by-id/ata-XYZ-part", None),
+        ];
+
+        for (part_name, expected_disk) in expected {
+            assert_eq!(disk_name(part_name), expected_disk, "{}", part_name);
+        }
+    }
+}
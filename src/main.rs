@@ -1,39 +1,426 @@
-mod disk;
-mod error;
-mod linux;
-mod partition;
+mod cli;
 
-use std::io::{self, Read};
+use cli::{Command, MappingFormat};
+use sfdisk_sort_rs::{
+    diff, fstab, is_sfdisk_json, parse_sfdisk_disks, parse_sfdisk_json_disk,
+    read_gpt_disk, render, report, to_sfdisk_json, write_gpt_disk, Alignment,
+    Arrangement, Disk, LayoutError, NameSpans, ParseError, Renaming, Sysfs, SysfsError,
+};
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process::ExitCode;
 use anyhow::{Error, Context};
 
-fn main() -> Result<(), Error> {
-    let sfdisk_output = get_stdin_string()?;
-    let mut this_disk = disk::parse_sfdisk_full_disk(sfdisk_output)?;
+const README_HINT: &str = "# See https://github.com/artnoi43/sfdisk-sort-rs/blob/main/README.md to see what to do whith this output";
+
+/// Exit status of `--check` when the partitions are out of order.
+const EXIT_UNSORTED: u8 = 1;
+/// Exit status when the arguments or the input could not be read or parsed.
+const EXIT_PARSE_ERROR: u8 = 2;
+/// Exit status of every other error.
+const EXIT_FAILURE: u8 = 3;
+
+fn main() -> ExitCode {
+    let args = match cli::parse(std::env::args().skip(1).collect()) {
+        Ok(args) => args,
+        Err(err) => return fail(err, EXIT_PARSE_ERROR),
+    };
+    match args.command {
+        Command::Help => {
+            println!("{}", cli::USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Command::Version => {
+            println!("sfdisk-sort-rs {}", env!("CARGO_PKG_VERSION"));
+            return ExitCode::SUCCESS;
+        }
+        _ => {}
+    }
+
+    // Name of the input in error messages, like a compiler's file name
+    let source = args.input.as_deref().unwrap_or("<stdin>");
+
+    let parsed = match args.image.as_deref() {
+        Some(path) => read_image(path),
+        None => get_input_string(args.input.as_deref()).and_then(parse_disks),
+    };
+    let (disks, is_json) = match parsed {
+        Ok(parsed) => parsed,
+        Err(err) => return fail_in(err, EXIT_PARSE_ERROR, &NameSpans::default(), source),
+    };
+    // Where the names are in the input, as the disks are consumed by the commands
+    let mut spans = NameSpans::default();
+    for this_disk in &disks {
+        spans.extend(this_disk.spans());
+    }
+
+    if let Some(sysfs) = &args.sysfs {
+        match stale_warnings(&disks, sysfs) {
+            Ok(warnings) => {
+                for warning in warnings {
+                    eprintln!("warning: {}", warning);
+                }
+            }
+            Err(err) => return fail(err, EXIT_FAILURE),
+        }
+    }
+
+    if args.command == Command::Check {
+        return match check(&disks, &args.arrangement) {
+            Ok(unsorted) if unsorted.is_empty() => ExitCode::SUCCESS,
+            Ok(unsorted) => {
+                for name in unsorted {
+                    eprintln!("{}: partitions are out of order", name);
+                }
+                ExitCode::from(EXIT_UNSORTED)
+            }
+            Err(err) => fail_in(err, EXIT_FAILURE, &spans, source),
+        };
+    }
+
+    match run(args.command, disks, &args.arrangement, is_json)
+        .and_then(|output| write_output(args.output.as_deref(), &output))
+    {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => fail_in(err, EXIT_FAILURE, &spans, source),
+    }
+}
+
+/// Prints `err` with its causes the same way `main` returning an error would,
+/// and returns `status` as the exit code.
+fn fail(err: Error, status: u8) -> ExitCode {
+    eprintln!("Error: {:?}", err);
+    ExitCode::from(status)
+}
+
+/// Like `fail`, but points at the offending line of the input (named `source`) if `err`
+/// comes from a `ParseError`, or from a `LayoutError` about a name in `spans`.
+fn fail_in(err: Error, status: u8, spans: &NameSpans, source: &str) -> ExitCode {
+    let located = err.chain().find_map(|cause| {
+        if let Some(parse_err) = cause.downcast_ref::<ParseError>() {
+            return Some((parse_err.to_string(), parse_err.span().clone()));
+        }
+        let layout_err = cause.downcast_ref::<LayoutError>()?;
+        Some((layout_err.to_string(), layout_err.span(spans)?))
+    });
+
+    match located {
+        Some((message, span)) => {
+            eprint!("{}", render(&message, &span, source));
+            ExitCode::from(status)
+        }
+        None => fail(err, status),
+    }
+}
+
+/// Parses the `sfdisk -d` or `sfdisk -J` output, returning the disks and whether it was JSON.
+/// The `sfdisk -d` output can hold several concatenated dumps, while JSON is always one disk.
+fn parse_disks(sfdisk_output: String) -> anyhow::Result<(Vec<Disk>, bool)> {
+    if is_sfdisk_json(&sfdisk_output) {
+        return Ok((vec![parse_sfdisk_json_disk(sfdisk_output)?], true));
+    }
+
+    Ok((parse_sfdisk_disks(sfdisk_output)?, false))
+}
+
+/// Reads the GPT of the disk image at `path`, as one disk printed as `sfdisk -d` output.
+fn read_image(path: &str) -> anyhow::Result<(Vec<Disk>, bool)> {
+    let this_disk = read_gpt_disk(path)
+        .with_context(|| format!("failed to read the GPT of {}", path))?;
+
+    Ok((vec![this_disk], false))
+}
+
+/// Refuses layouts that sfdisk would reject, or that would corrupt data if applied,
+/// and misaligned partitions if `align` is set. The problems of every disk are printed
+/// to stderr before failing.
+fn check_layout(disks: &[Disk], align: Option<&Alignment>) -> anyhow::Result<()> {
+    let mut problems = 0;
+    for this_disk in disks {
+        let diagnostics = match align {
+            Some(alignment) => this_disk.validate_aligned(alignment)?,
+            None => this_disk.validate(),
+        };
+        for diagnostic in &diagnostics {
+            eprintln!("error: {}", diagnostic);
+        }
+        problems += diagnostics.len();
+    }
+    if problems == 0 {
+        return Ok(());
+    }
+
+    Err(Error::msg(format!(
+        "found {} problem(s) in the partition layout",
+        problems
+    )))
+}
+
+/// Returns how the disks differ from the running kernel's view in `sysfs`, for `--sysfs`.
+/// The differences are only warnings, as a dump is often taken to change the table.
+fn stale_warnings(disks: &[Disk], sysfs: &Sysfs) -> anyhow::Result<Vec<String>> {
+    let mut warnings: Vec<String> = Vec::new();
+    for this_disk in disks {
+        let diagnostics = match this_disk.validate_sysfs(sysfs) {
+            // e.g. a dump of another machine's disk, nothing to compare with
+            Err(err @ SysfsError::NoDevice { .. }) => {
+                warnings.push(err.to_string());
+                continue;
+            }
+            diagnostics => diagnostics.with_context(|| {
+                format!("failed to cross-check {} with sysfs", this_disk.name())
+            })?,
+        };
+        warnings.extend(diagnostics.iter().map(|diagnostic| diagnostic.to_string()));
+    }
+
+    Ok(warnings)
+}
+
+/// Returns the names of the disks whose partitions are not arranged yet, for `--check`.
+fn check(disks: &[Disk], arrangement: &Arrangement) -> anyhow::Result<Vec<String>> {
+    check_pins(disks, arrangement)?;
+    check_layout(disks, None)?;
+
+    let mut unsorted: Vec<String> = Vec::new();
+    for this_disk in disks {
+        match this_disk.is_sorted_with(&arrangement_for(this_disk, arrangement)) {
+            Ok(true) => {}
+            Ok(false) => unsorted.push(this_disk.name().to_string()),
+            Err(err) => {
+                return Err(Error::from(err))
+                    .with_context(|| String::from("failed to rearrange disk partitions"))
+            }
+        }
+    }
 
-    // Rearrange disk partitions by start_block
-    this_disk
-        .rearrange()
-        .expect("failed to rearrange disk partitions");
+    Ok(unsorted)
+}
 
-    print_disk(this_disk);
+/// Refuses pins that match no partition on any of the disks.
+fn check_pins(disks: &[Disk], arrangement: &Arrangement) -> anyhow::Result<()> {
+    for pin in &arrangement.pins {
+        let matched = disks.iter().any(|this_disk| {
+            this_disk
+                .partitions()
+                .iter()
+                .any(|part| pin.target.matches(part))
+        });
+        if !matched {
+            return Err(Error::from(LayoutError::PinNotFound(pin.to_string())))
+                .with_context(|| String::from("failed to rearrange disk partitions"));
+        }
+    }
 
-    println!();
-    println!("# See https://github.com/artnoi43/sfdisk-sort-rs/blob/main/README.md to see what to do whith this output");
     Ok(())
 }
 
-/// Prints disk in `sfdisk -d` dump format. `disk::Disk` does not implements Display,
-/// so this is how the program prints a `disk::Disk`
-fn print_disk(this_disk: disk::Disk) {
-    for header_line in this_disk.header_lines {
-        println!("{}", header_line);
+/// Returns `arrangement` with only the pins of partitions on `this_disk`,
+/// as the pins of a multi-disk input are given for all disks at once.
+fn arrangement_for(this_disk: &Disk, arrangement: &Arrangement) -> Arrangement {
+    let pins = arrangement
+        .pins
+        .iter()
+        .filter(|pin| {
+            this_disk
+                .partitions()
+                .iter()
+                .any(|part| pin.target.matches(part))
+        })
+        .cloned()
+        .collect();
+
+    let mut narrowed = arrangement.clone();
+    narrowed.pins = pins;
+    narrowed
+}
+
+/// Runs `command` on the parsed disks, each one on its own, and returns the text to write out.
+fn run(
+    command: Command,
+    mut disks: Vec<Disk>,
+    arrangement: &Arrangement,
+    is_json: bool,
+) -> anyhow::Result<String> {
+    let reports: Option<Vec<String>> = match &command {
+        Command::Info => Some(disks.iter().map(report::disk_info).collect()),
+        Command::Free => Some(disks.iter().map(report::free_space).collect()),
+        Command::Align { align } => Some(
+            disks
+                .iter()
+                .map(|this_disk| report::alignment(this_disk, align))
+                .collect::<anyhow::Result<Vec<String>>>()?,
+        ),
+        _ => None,
+    };
+    if let Some(reports) = reports {
+        return Ok(format!("{}\n", reports.join("\n\n")));
+    }
+
+    if let Command::Validate { align } = &command {
+        check_layout(&disks, align.as_ref())?;
+        let mut output = String::new();
+        for this_disk in &disks {
+            output.push_str(&format!("{}: no problems found\n", this_disk.name()));
+        }
+        return Ok(output);
+    }
+    check_layout(&disks, None)?;
+    check_pins(&disks, arrangement)?;
+
+    let original = format_disks(&disks, is_json)?;
+
+    // Rearrange disk partitions, by start_block unless --sort-by or --pin say otherwise
+    let mut renamings: Vec<Renaming> = Vec::new();
+    for this_disk in &mut disks {
+        match this_disk.rearrange_with(&arrangement_for(this_disk, arrangement)) {
+            Ok(disk_renamings) => renamings.extend(disk_renamings),
+            Err(err) => {
+                return Err(Error::from(err)).with_context(|| {
+                    String::from("failed to rearrange disk partitions")
+                });
+            }
+        }
+    }
+
+    match command {
+        // Print which old partition became which new one instead of the table
+        Command::Sort {
+            mapping: Some(MappingFormat::Table),
+        } => Ok(format!("{}\n", report::mapping_table(&renamings))),
+        Command::Sort {
+            mapping: Some(MappingFormat::Json),
+        } => Ok(format!("{}\n", report::mapping_json(&renamings)?)),
+        Command::Sort { mapping: None } => {
+            let sorted = format_disks(&disks, is_json)?;
+            // JSON output must stay parsable, so only the text dump gets the hint
+            if is_json {
+                return Ok(sorted);
+            }
+            Ok(format!("{}\n{}\n", sorted, README_HINT))
+        }
+        Command::Diff { color } => {
+            let sorted = format_disks(&disks, is_json)?;
+            // Note which old partition became which new one on each changed partition line
+            let annotate = |edit: &diff::Edit| match edit {
+                diff::Edit::Delete(line) => {
+                    report::designation_note(&renamings, line, true)
+                }
+                diff::Edit::Insert(line) => {
+                    report::designation_note(&renamings, line, false)
+                }
+                diff::Edit::Equal(_) => None,
+            };
+            let style = diff::Style {
+                color,
+                annotate: Some(&annotate),
+            };
+
+            Ok(diff::unified(
+                &original,
+                &sorted,
+                "original",
+                "sorted",
+                diff::DEFAULT_CONTEXT,
+                style,
+            ))
+        }
+        Command::Fstab { path, diff, color } => {
+            fstab_output(&path, diff, color, &renamings)
+        }
+        Command::Write { path, dry_run } => {
+            let changes = write_gpt_disk(&path, &disks[0], dry_run)
+                .with_context(|| format!("failed to write the GPT of {}", path))?;
+
+            Ok(format!(
+                "{}\n",
+                report::gpt_changes(&path, &changes, dry_run)
+            ))
+        }
+        Command::Validate { .. }
+        | Command::Info
+        | Command::Free
+        | Command::Align { .. }
+        | Command::Check
+        | Command::Help
+        | Command::Version => {
+            unreachable!("command is handled before rearranging")
+        }
+    }
+}
+
+/// Formats each disk with `format_disk`, separated by blank lines.
+fn format_disks(disks: &[Disk], is_json: bool) -> anyhow::Result<String> {
+    let texts = disks
+        .iter()
+        .map(|this_disk| format_disk(this_disk, is_json))
+        .collect::<anyhow::Result<Vec<String>>>()?;
+
+    Ok(texts.join("\n"))
+}
+
+/// Formats disk in the `sfdisk -d` dump format, or in the `sfdisk -J` format if `is_json`.
+/// `Disk` does not implements Display, so this is how the program prints a `Disk`
+fn format_disk(this_disk: &Disk, is_json: bool) -> anyhow::Result<String> {
+    if is_json {
+        return Ok(format!("{}\n", to_sfdisk_json(this_disk)?));
+    }
+
+    let mut text = format!("{}\n\n", this_disk.header());
+    for each_partition in this_disk.partitions() {
+        text.push_str(&format!("{}\n", each_partition));
     }
-    for each_partition in this_disk.partitions {
-        println!("{}", each_partition);
+
+    Ok(text)
+}
+
+/// Returns the fstab at `path` with its device paths renamed, or the unified diff
+/// of the changes if `diff` is set. The fstab file itself is never modified.
+fn fstab_output(
+    path: &str,
+    diff: bool,
+    color: bool,
+    renamings: &[Renaming],
+) -> anyhow::Result<String> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) => {
+            return Err(Error::from(err))
+                .with_context(|| format!("failed to read fstab {}", path));
+        }
+    };
+    let rewritten = fstab::rewrite(&content, renamings);
+
+    if diff {
+        let new_label = format!("{} (sorted)", path);
+        let style = diff::Style {
+            color,
+            ..Default::default()
+        };
+        return Ok(diff::unified(
+            &content,
+            &rewritten,
+            path,
+            &new_label,
+            diff::DEFAULT_CONTEXT,
+            style,
+        ));
     }
+
+    Ok(rewritten)
 }
 
-fn get_stdin_string() -> anyhow::Result<String> {
+/// Reads the sfdisk output from the file at `path`, or from stdin if there is no path.
+fn get_input_string(path: Option<&str>) -> anyhow::Result<String> {
+    if let Some(path) = path {
+        return match fs::read_to_string(path) {
+            Ok(text) => Ok(text),
+            Err(err) => Err(Error::from(err))
+                .with_context(|| format!("failed to read sfdisk output from {}", path)),
+        };
+    }
+
     let mut buf = String::new();
     let mut stdin = io::stdin();
 
@@ -45,9 +432,23 @@ fn get_stdin_string() -> anyhow::Result<String> {
     Ok(buf)
 }
 
+/// Writes `output` to the file at `path`, or to stdout if there is no path.
+fn write_output(path: Option<&str>, output: &str) -> anyhow::Result<()> {
+    let result = match path {
+        Some(path) => fs::write(path, output),
+        None => io::stdout().write_all(output.as_bytes()),
+    };
+
+    result.with_context(|| {
+        format!("failed to write output to {}", path.unwrap_or("stdout"))
+    })
+}
+
 #[cfg(test)]
 mod test_main {
-    use crate::disk::parse_sfdisk_full_disk;
+    use super::{check, parse_disks, run, stale_warnings, Arrangement, Disk, README_HINT};
+    use crate::cli::{Command, MappingFormat};
+    use sfdisk_sort_rs::{parse_sfdisk_full_disk, parse_sfdisk_json_disk, Numbering, SortKey};
 
     #[test]
     fn test_prog() {
@@ -62,14 +463,226 @@ mod test_main {
         let pretty_disk_input = fs::read_to_string(pretty_disk_file)
             .expect("failed to read pretty test text file");
 
-        let mut ugly_disk =
-            parse_sfdisk_full_disk(String::from(ugly_disk_input)).unwrap();
+        let mut ugly_disk = parse_sfdisk_full_disk(ugly_disk_input).unwrap();
+
+        let pretty_disk = parse_sfdisk_full_disk(pretty_disk_input).unwrap();
+
+        ugly_disk.rearrange().expect("failed to rearrange");
+
+        assert_eq!(ugly_disk, pretty_disk);
+    }
+
+    #[test]
+    fn test_prog_json() {
+        use std::fs;
+
+        let ugly_disk_input = fs::read_to_string("./assets/sfdisk_output_ugly.json")
+            .expect("failed to read ugly test JSON file");
+        let pretty_disk_input = fs::read_to_string("./assets/sfdisk_output.json")
+            .expect("failed to read pretty test JSON file");
+
+        let mut ugly_disk = parse_sfdisk_json_disk(ugly_disk_input).unwrap();
+        let pretty_disk = parse_sfdisk_json_disk(pretty_disk_input).unwrap();
+
+        ugly_disk.rearrange().expect("failed to rearrange");
+
+        assert_eq!(ugly_disk, pretty_disk);
+    }
+
+    #[test]
+    fn test_prog_dos() {
+        use std::fs;
 
-        let pretty_disk =
-            parse_sfdisk_full_disk(String::from(pretty_disk_input)).unwrap();
+        let ugly_disk_input = fs::read_to_string("./assets/sfdisk_output_dos_ugly.txt")
+            .expect("failed to read ugly DOS test text file");
+        let pretty_disk_input = fs::read_to_string("./assets/sfdisk_output_dos.txt")
+            .expect("failed to read pretty DOS test text file");
+
+        let mut ugly_disk = parse_sfdisk_full_disk(ugly_disk_input).unwrap();
+        let pretty_disk = parse_sfdisk_full_disk(pretty_disk_input).unwrap();
 
         ugly_disk.rearrange().expect("failed to rearrange");
 
         assert_eq!(ugly_disk, pretty_disk);
     }
+
+    #[test]
+    fn test_run() {
+        use std::fs;
+
+        let ugly_disk_input = fs::read_to_string("./assets/sfdisk_output_ugly.txt")
+            .expect("failed to read ugly test text file");
+        let pretty_disk_input = fs::read_to_string("./assets/sfdisk_output.txt")
+            .expect("failed to read pretty test text file");
+
+        let ugly_disk = parse_sfdisk_full_disk(ugly_disk_input.clone()).unwrap();
+        let sorted = run(
+            Command::Sort { mapping: None },
+            vec![ugly_disk],
+            &Arrangement::default(),
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            sorted,
+            format!("{}\n\n{}\n", pretty_disk_input, README_HINT)
+        );
+
+        let ugly_disk = parse_sfdisk_full_disk(ugly_disk_input.clone()).unwrap();
+        let diff = run(
+            Command::Diff { color: false },
+            vec![ugly_disk],
+            &Arrangement::default(),
+            false,
+        )
+        .unwrap();
+        assert!(diff.starts_with("--- original\n+++ sorted\n@@ "));
+        assert!(diff.contains("\n-/dev/sda5 : start=    67520512"));
+        assert!(diff.contains("\n+/dev/sda3 : start=    67520512"));
+        assert!(diff.contains("OOOOOOOOOOOO  # 5 -> 3\n"));
+
+        let ugly_disk = parse_sfdisk_full_disk(ugly_disk_input).unwrap();
+        let validated = run(
+            Command::Validate { align: None },
+            vec![ugly_disk],
+            &Arrangement::default(),
+            false,
+        )
+        .unwrap();
+        assert_eq!(validated, "/dev/sda: no problems found\n");
+    }
+
+    #[test]
+    fn test_check() {
+        use std::fs;
+
+        let ugly_disk_input = fs::read_to_string("./assets/sfdisk_output_ugly.txt")
+            .expect("failed to read ugly test text file");
+        let pretty_disk_input = fs::read_to_string("./assets/sfdisk_output.txt")
+            .expect("failed to read pretty test text file");
+
+        let ugly_disk = parse_sfdisk_full_disk(ugly_disk_input).unwrap();
+        let pretty_disk = parse_sfdisk_full_disk(pretty_disk_input).unwrap();
+
+        let by_type =
+            Arrangement::new(SortKey::TypeGroups, Vec::new(), Numbering::default());
+        let pinned = Arrangement::new(
+            SortKey::default(),
+            vec!["/dev/sda4=1".parse().unwrap()],
+            Numbering::default(),
+        );
+
+        let sda = vec![String::from("/dev/sda")];
+        let unsorted = |disk: &Disk, arrangement: &Arrangement| {
+            check(std::slice::from_ref(disk), arrangement).unwrap()
+        };
+
+        assert_eq!(unsorted(&ugly_disk, &Arrangement::default()), sda);
+        assert!(unsorted(&pretty_disk, &Arrangement::default()).is_empty());
+        // The ESP is already partition 1
+        assert!(unsorted(&pretty_disk, &by_type).is_empty());
+        assert_eq!(unsorted(&pretty_disk, &pinned), sda);
+    }
+
+    #[test]
+    fn test_multiple_disks() {
+        use std::fs;
+
+        let ugly_input = fs::read_to_string("./assets/sfdisk_output_multi_ugly.txt")
+            .expect("failed to read ugly multi-disk test text file");
+        let pretty_input = fs::read_to_string("./assets/sfdisk_output_multi.txt")
+            .expect("failed to read pretty multi-disk test text file");
+
+        let (ugly_disks, _) = parse_disks(ugly_input.clone()).unwrap();
+        let (pretty_disks, _) = parse_disks(pretty_input).unwrap();
+        assert_eq!(
+            check(&ugly_disks, &Arrangement::default()).unwrap(),
+            vec![String::from("/dev/sda"), String::from("/dev/vda")]
+        );
+        assert!(check(&pretty_disks, &Arrangement::default())
+            .unwrap()
+            .is_empty());
+
+        let sorted = run(
+            Command::Sort { mapping: None },
+            ugly_disks.clone(),
+            &Arrangement::default(),
+            false,
+        )
+        .unwrap();
+        let expected = fs::read_to_string("./assets/sfdisk_output_multi.txt").unwrap();
+        assert_eq!(sorted, format!("{}\n{}\n", expected, README_HINT));
+
+        // Pins apply to the disk of the pinned partition
+        let pinned = Arrangement::new(
+            SortKey::default(),
+            vec!["/dev/vda1=1".parse().unwrap()],
+            Numbering::default(),
+        );
+        let mapping = run(
+            Command::Sort {
+                mapping: Some(MappingFormat::Table),
+            },
+            ugly_disks.clone(),
+            &pinned,
+            false,
+        )
+        .unwrap();
+        assert!(mapping.contains("\n/dev/sda2  /dev/sda1"));
+        assert!(mapping.contains("\n/dev/vda1  /dev/vda1"));
+
+        let unknown = Arrangement::new(
+            SortKey::default(),
+            vec!["/dev/vdb1=1".parse().unwrap()],
+            Numbering::default(),
+        );
+        assert!(run(
+            Command::Diff { color: false },
+            ugly_disks.clone(),
+            &unknown,
+            false
+        )
+        .is_err());
+
+        // The problems of every disk are reported, not only those of the first one
+        let mut broken = ugly_disks;
+        for this_disk in &mut broken {
+            let partitions = this_disk.partitions_mut();
+            let start = partitions[0].start_block();
+            partitions[1].set_start_block(start);
+        }
+        let err = check(&broken, &Arrangement::default()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "found 2 problem(s) in the partition layout"
+        );
+    }
+
+    #[test]
+    fn test_stale_warnings() {
+        use sfdisk_sort_rs::Sysfs;
+
+        let input = std::fs::read_to_string("./assets/sfdisk_output.txt")
+            .expect("failed to read test text file");
+        let (disks, _) = parse_disks(input).unwrap();
+
+        let warnings =
+            stale_warnings(&disks, &Sysfs::new("./assets/sysfs_block")).unwrap();
+        assert_eq!(
+            warnings,
+            vec![
+                "partition /dev/sda3 starts at 67520512 with size 33554432, but the kernel has it at 67520512 with size 16777216",
+                "partition /dev/sda4 is not in the kernel's partition table",
+                "the kernel has partition /dev/sda5, which is not in the dump",
+            ]
+        );
+
+        // A disk the kernel does not have is only a warning
+        let missing =
+            stale_warnings(&disks, &Sysfs::new("./assets/no_such_dir")).unwrap();
+        assert_eq!(
+            missing,
+            vec!["device /dev/sda is not in ./assets/no_such_dir"]
+        );
+    }
 }
@@ -1,66 +1,411 @@
-pub mod parse;
+pub(crate) mod parse;
+pub(crate) mod token;
 
-use crate::linux::block;
-
-use anyhow::Result;
+use crate::error::{LayoutError, ParseError, Span};
+use crate::linux::naming;
 
 /// Represents what matters for sfdisk-sort to reassign the names in the partition table.
-/// Fields `designation` and `start_block` are used for sorting.
-// Trait Clone is now only used for testing - TODO: remove?
-#[derive(Default, Debug, PartialEq, Clone)]
+/// Fields `designation` and `start_block` are used for sorting, while the other fields
+/// are the typed values of the sfdisk `key=value` fields of the partition line.
+#[derive(Default, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Partition {
     // For sorting
     pub(crate) designation: usize,
-    pub(crate) start_block: usize,
+    pub(crate) start_block: u64,
 
     // For reconstructing sfdisk dump output
     pub(crate) name: String, // This will be full path, e.g. /dev/sda1
-    pub(crate) extras: Vec<String>,
+    pub(crate) size: Option<u64>,
+    pub(crate) part_type: Option<PartitionType>,
+    pub(crate) uuid: Option<String>,
+    pub(crate) label: Option<String>, // GPT partition name, i.e. sfdisk `name=`
+    pub(crate) attrs: Vec<String>,
+    pub(crate) bootable: bool,
+    pub(crate) extras: Vec<String>, // Unknown fields, kept verbatim for round-tripping
+
+    // The fields of the parsed line as spelled, printed back as long as they are unchanged
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) fields: Vec<RawField>,
+}
+
+/// One `key=value` field of a parsed partition line, as it was spelled in the dump.
+#[derive(Default, Debug, Clone)]
+pub(crate) struct RawField {
+    /// The text before the field, e.g. `, `
+    pub(crate) lead: String,
+    /// The field, e.g. `type=07`
+    pub(crate) text: String,
+}
+
+impl RawField {
+    /// Returns the key of the field, `type` for the old sfdisk `Id=`.
+    fn key(&self) -> &str {
+        let key = match self.text.split_once('=') {
+            Some((key, _)) => key.trim_end(),
+            None => self.text.as_str(),
+        };
+        match key {
+            "Id" => "type",
+            _ => key,
+        }
+    }
+}
+
+/// Partitions are equal when their values are, however the dump spelled them.
+impl PartialEq for Partition {
+    fn eq(&self, other: &Self) -> bool {
+        self.designation == other.designation
+            && self.start_block == other.start_block
+            && self.name == other.name
+            && self.size == other.size
+            && self.part_type == other.part_type
+            && self.uuid == other.uuid
+            && self.label == other.label
+            && self.attrs == other.attrs
+            && self.bootable == other.bootable
+            && self.extras == other.extras
+    }
+}
+
+/// Partition type as written in the sfdisk `type=` field.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum PartitionType {
+    /// GPT partition type GUID, e.g. `C12A7328-F81F-11D2-BA4B-00A0C93EC93B`
+    Gpt(String),
+    /// MBR/DOS partition type byte, written by sfdisk in hex, e.g. `83`
+    Mbr(u8),
+    /// Anything else sfdisk understands, e.g. the shortcuts `L` or `swap`
+    Alias(String),
+}
+
+impl PartitionType {
+    /// Guesses the kind of type from the `type=` value text.
+    pub fn parse(s: &str) -> Self {
+        if is_guid(s) {
+            return Self::Gpt(String::from(s));
+        }
+        if (1..=2).contains(&s.len()) {
+            if let Ok(code) = u8::from_str_radix(s, 16) {
+                return Self::Mbr(code);
+            }
+        }
+
+        Self::Alias(String::from(s))
+    }
+}
+
+impl std::fmt::Display for PartitionType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Gpt(guid) => write!(f, "{}", guid),
+            Self::Mbr(code) => write!(f, "{:x}", code),
+            Self::Alias(alias) => write!(f, "{}", alias),
+        }
+    }
+}
+
+/// Reports whether `s` has the `8-4-4-4-12` hex digit GUID shape.
+fn is_guid(s: &str) -> bool {
+    let groups: Vec<&str> = s.split('-').collect();
+    let lens = [8, 4, 4, 4, 12];
+
+    groups.len() == lens.len()
+        && groups.iter().zip(lens.iter()).all(|(group, len)| {
+            group.len() == *len && group.chars().all(|c| c.is_ascii_hexdigit())
+        })
+}
+
+/// Keys of the typed fields, in the order `sfdisk -d` prints them.
+const FIELD_KEYS: [&str; 7] =
+    ["start", "size", "type", "uuid", "name", "attrs", "bootable"];
+
+impl Partition {
+    /// Formats the typed field `key` the way `sfdisk -d` does, if it is set.
+    fn canonical_field(&self, key: &str) -> Option<String> {
+        match key {
+            "start" => Some(format!("start={:>12}", self.start_block)),
+            "size" => self.size.map(|size| format!("size={:>12}", size)),
+            "type" => self.part_type.as_ref().map(|t| format!("type={}", t)),
+            "uuid" => self.uuid.as_ref().map(|uuid| format!("uuid={}", uuid)),
+            "name" => self
+                .label
+                .as_ref()
+                .map(|label| format!("name={}", token::quote(label))),
+            "attrs" => (!self.attrs.is_empty())
+                .then(|| format!("attrs=\"{}\"", self.attrs.join(" "))),
+            "bootable" => self.bootable.then(|| String::from("bootable")),
+            _ => None,
+        }
+    }
+
+    /// Reports whether the typed field `key` has the same value in `other`.
+    fn same_field(&self, other: &Partition, key: &str) -> bool {
+        match key {
+            "start" => self.start_block == other.start_block,
+            "size" => self.size == other.size,
+            "type" => self.part_type == other.part_type,
+            "uuid" => self.uuid == other.uuid,
+            "name" => self.label == other.label,
+            "attrs" => self.attrs == other.attrs,
+            "bootable" => self.bootable == other.bootable,
+            _ => false,
+        }
+    }
 }
 
 /// sfdisk-sort-rs uses this Display impl to reconstruct sfdisk output
-/// in the form `/dev/sda1 : start= 2048, size= 409600, type=C12A7328-F81F-11D2-BA4B-00A0C93EC93B, uuid=AAAAAAAA-BBBB-CCCC-DDDD-EEEEEEEEEEEE`
+/// in the form `/dev/sda1 : start=        2048, size=      409600, type=C12A7328-F81F-11D2-BA4B-00A0C93EC93B, uuid=AAAAAAAA-BBBB-CCCC-DDDD-EEEEEEEEEEEE`,
+/// using the same field order and padding as `sfdisk -d`.
+///
+/// The fields of a parsed line keep their order and spelling, e.g. `type=07` or unknown
+/// fields between known ones. Only the fields that changed since are reformatted, and
+/// the ones that were added are appended like sfdisk prints them.
 impl std::fmt::Display for Partition {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let joined_extras: String = self.extras.join(" ");
-        write!(
-            f,
-            "{0} : start= {1}, {2}",
-            self.name, self.start_block, joined_extras
-        )
+        write!(f, "{} : ", self.name)?;
+
+        let mut written: Vec<&str> = Vec::new();
+        let mut extras_written = vec![false; self.extras.len()];
+        for field in &self.fields {
+            let key = field.key();
+            let text = if FIELD_KEYS.contains(&key) {
+                if written.contains(&key) {
+                    continue;
+                }
+                written.push(key);
+                match parse::parse_field_text(&field.text) {
+                    Some(spelled) if self.same_field(&spelled, key) => {
+                        Some(field.text.clone())
+                    }
+                    _ => self.canonical_field(key),
+                }
+            } else {
+                let unwritten = self
+                    .extras
+                    .iter()
+                    .zip(extras_written.iter_mut())
+                    .find(|(extra, done)| !**done && **extra == field.text);
+                unwritten.map(|(extra, done)| {
+                    *done = true;
+                    extra.clone()
+                })
+            };
+
+            if let Some(text) = text {
+                write!(f, "{}{}", field.lead, text)?;
+            }
+        }
+
+        for key in FIELD_KEYS {
+            if written.contains(&key) {
+                continue;
+            }
+            if let Some(text) = self.canonical_field(key) {
+                let lead = if key == "start" { "" } else { ", " };
+                write!(f, "{}{}", lead, text)?;
+            }
+        }
+        for (extra, done) in self.extras.iter().zip(extras_written) {
+            if !done {
+                write!(f, ", {}", extra)?;
+            }
+        }
+
+        Ok(())
     }
 }
 
+/// Records how `Partition::redesignate` renamed a partition, e.g. `/dev/sda5` to `/dev/sda3`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Renaming {
+    /// Name before rearranging, e.g. `/dev/sda5`
+    pub old_name: String,
+    /// Number before rearranging
+    pub old_designation: usize,
+    /// Name after rearranging, e.g. `/dev/sda3`
+    pub new_name: String,
+    /// Number after rearranging
+    pub new_designation: usize,
+    /// Partition UUID, which rearranging keeps
+    pub uuid: Option<String>,
+}
+
 impl Partition {
+    /// Creates a partition named `name` (e.g. `/dev/sda1`) starting at `start_block`,
+    /// with no other fields set. The designation is the trailing number of `name`.
+    pub fn new(name: &str, start_block: u64) -> Result<Self, ParseError> {
+        Ok(Partition {
+            designation: designation_from_name(name)?,
+            start_block,
+            name: String::from(name),
+            ..Default::default()
+        })
+    }
+
+    /// Returns the partition number, e.g. 1 for `/dev/sda1`.
+    pub fn designation(&self) -> usize {
+        self.designation
+    }
+
+    /// Returns the first sector, i.e. sfdisk `start=`.
+    pub fn start_block(&self) -> u64 {
+        self.start_block
+    }
+
+    /// Returns the full device path, e.g. `/dev/sda1`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the size in sectors, i.e. sfdisk `size=`.
+    pub fn size(&self) -> Option<u64> {
+        self.size
+    }
+
+    /// Returns the partition type, i.e. sfdisk `type=`.
+    pub fn part_type(&self) -> Option<&PartitionType> {
+        self.part_type.as_ref()
+    }
+
+    /// Returns the partition UUID, i.e. sfdisk `uuid=`.
+    pub fn uuid(&self) -> Option<&str> {
+        self.uuid.as_deref()
+    }
+
+    /// Returns the GPT partition name, i.e. sfdisk `name=`.
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// Returns the GPT attributes, i.e. the words of sfdisk `attrs=`.
+    pub fn attrs(&self) -> &[String] {
+        &self.attrs
+    }
+
+    /// Reports whether the DOS boot flag is set, i.e. sfdisk `bootable`.
+    pub fn bootable(&self) -> bool {
+        self.bootable
+    }
+
+    /// Returns the unknown `key=value` fields, as they are written back to sfdisk.
+    pub fn extras(&self) -> &[String] {
+        &self.extras
+    }
+
+    /// Sets the first sector. The name is kept, see `Disk::partitions_mut`.
+    pub fn set_start_block(&mut self, start_block: u64) {
+        self.start_block = start_block;
+    }
+
+    /// Sets the size in sectors.
+    pub fn set_size(&mut self, size: Option<u64>) {
+        self.size = size;
+    }
+
+    /// Sets the partition type.
+    pub fn set_part_type(&mut self, part_type: Option<PartitionType>) {
+        self.part_type = part_type;
+    }
+
+    /// Sets the partition UUID.
+    pub fn set_uuid(&mut self, uuid: Option<String>) {
+        self.uuid = uuid;
+    }
+
+    /// Sets the GPT partition name.
+    pub fn set_label(&mut self, label: Option<String>) {
+        self.label = label;
+    }
+
+    /// Sets the GPT attributes.
+    pub fn set_attrs(&mut self, attrs: Vec<String>) {
+        self.attrs = attrs;
+    }
+
+    /// Sets the DOS boot flag.
+    pub fn set_bootable(&mut self, bootable: bool) {
+        self.bootable = bootable;
+    }
+
+    /// Renames the partition of disk `disk_name` to `new_designation`, returning the old
+    /// and new names.
     pub fn redesignate(
         &mut self,
-        blk_dev: block::LinuxBlockDevice,
+        disk_name: &str,
         new_designation: usize,
-    ) -> Result<()> {
-        let (prefix, _part_num) =
-            match block::linux_part_prefix_and_part_num(blk_dev, &self.name) {
-                Ok((pref, partn)) => (pref, partn),
-                Err(err) => return Err(err),
-            };
+    ) -> Result<Renaming, LayoutError> {
+        if !naming::is_part_of(disk_name, &self.name) {
+            return Err(LayoutError::PartitionName {
+                device: String::from(disk_name),
+                partition: self.name.clone(),
+            });
+        }
 
-        self.name = format!("{}{}", prefix, new_designation);
+        let renaming = Renaming {
+            old_name: self.name.clone(),
+            old_designation: self.designation,
+            new_name: naming::part_name(disk_name, new_designation),
+            new_designation,
+            uuid: self.uuid.clone(),
+        };
+        self.name = renaming.new_name.clone();
         self.designation = new_designation;
 
-        Ok(())
+        Ok(renaming)
+    }
+
+    /// Returns the last block of the partition, if its size is known and the end fits in
+    /// a block number, see `Diagnostic::EndOverflow`.
+    pub fn end_block(&self) -> Option<u64> {
+        self.size
+            .and_then(|size| self.start_block.checked_add(size.saturating_sub(1)))
+    }
+
+    /// Reports whether this is a DOS extended partition (types `5`, `f` and `85`),
+    /// i.e. the container for logical partitions.
+    pub fn is_extended(&self) -> bool {
+        matches!(self.part_type, Some(PartitionType::Mbr(0x05 | 0x0f | 0x85)))
+    }
+}
+
+/// Parses the partition number at the end of a partition name, e.g. 10 for `/dev/sda10`,
+/// see `naming::disk_name`. Error spans point into `name`, with line number 0.
+pub(crate) fn designation_from_name(name: &str) -> Result<usize, ParseError> {
+    let part_num = match naming::disk_name(name) {
+        Some(disk_name) => naming::part_num(disk_name, name).unwrap_or_default(),
+        None => {
+            return Err(ParseError::MissingPartitionNumber(Span::new(
+                0,
+                0..name.len(),
+                name,
+            )));
+        }
+    };
+
+    let span = Span::new(0, name.len() - part_num.len()..name.len(), name);
+    match str::parse::<usize>(part_num) {
+        Ok(0) => Err(ParseError::ZeroPartitionNumber(span)),
+        Ok(num) => Ok(num),
+        Err(_) => Err(ParseError::InvalidNumber(span)),
     }
 }
 
 #[cfg(test)]
-pub mod partition_tests {
+pub(crate) mod partition_tests {
     use super::parse;
-    use super::Partition;
+    use super::{Partition, PartitionType};
     use crate::disk::Disk;
     use crate::linux::block;
 
     impl Partition {
         pub(crate) fn new_from_start_block(
             designation: usize,
-            start_block: usize,
+            start_block: u64,
             blk: block::LinuxBlockDevice,
         ) -> Self {
             let part_name = match blk {
@@ -73,48 +418,97 @@ pub mod partition_tests {
                 designation,
                 start_block,
                 name: part_name,
-                extras: Vec::new(),
+                ..Default::default()
             }
         }
     }
 
     impl Disk {
-        pub fn new_disk_without_parts(
+        pub(crate) fn new_disk_without_parts(
             disk_name: &str,
         ) -> Result<(Self, block::LinuxBlockDevice), String> {
             if let Some(correct_linux_device) = block::linux_blk_name(disk_name) {
-                let mut this_disk = Disk::default();
-                this_disk.name = String::from(disk_name);
-                this_disk.linux_block_device = correct_linux_device;
+                let this_disk = Disk {
+                    name: String::from(disk_name),
+                    linux_block_device: Some(correct_linux_device),
+                    ..Default::default()
+                };
                 return Ok((this_disk, correct_linux_device));
             }
 
-            Err(format!(
-                "disk name does match known Linux block device name (e.g. sdX, vdX, or nvmeXnY)"
+            Err(String::from(
+                "disk name does match known Linux block device name (e.g. sdX, vdX, or nvmeXnY)",
             ))
         }
     }
 
-    // Test if the Display formatted text is indeed parsable.
+    // Test if the Display formatted text is indeed parsable, and parses back to the same partition.
     #[test]
     fn test_display() {
         let part = Partition {
             designation: 1,
             name: String::from("/dev/sda1"),
             start_block: 69,
-            extras: vec![
-                String::from("size="),
-                String::from("60086239,"),
-                String::from("type=0FC63DAF-8483-4772-8E79-3D69D8477DE4,"),
-                String::from("uuid=AAAAAAAA-BBBB-CCCC-DDDD-EEEEEEEEEEEE,"),
-                String::from("it"),
-                String::from("ain't"),
-                String::from("me"),
-                String::from("babe"),
-            ],
+            size: Some(60086239),
+            part_type: Some(PartitionType::parse("0FC63DAF-8483-4772-8E79-3D69D8477DE4")),
+            uuid: Some(String::from("AAAAAAAA-BBBB-CCCC-DDDD-EEEEEEEEEEEE")),
+            label: Some(String::from("root")),
+            attrs: vec![String::from("RequiredPartition"), String::from("GUID:63")],
+            extras: vec![String::from("it ain't me    babe")],
+            ..Default::default()
         };
 
-        assert!(parse::is_sfdisk_partition_line(&format!("{}", part)));
+        let line = format!("{}", part);
+        assert!(parse::is_sfdisk_partition_line(&line));
+        assert_eq!(parse::parse_sfdisk_partition_line(&line).unwrap().0, part);
+    }
+
+    #[test]
+    fn test_new() {
+        let mut part = Partition::new("/dev/nvme0n1p12", 2048).unwrap();
+        assert_eq!(part.designation(), 12);
+        assert_eq!(part.name(), "/dev/nvme0n1p12");
+        assert_eq!(part.start_block(), 2048);
+        assert_eq!(part.size(), None);
+
+        part.set_size(Some(409600));
+        part.set_label(Some(String::from("EFI system")));
+        part.set_bootable(true);
+        assert_eq!(part.end_block(), Some(411647));
+        assert_eq!(part.label(), Some("EFI system"));
+        assert!(part.bootable());
+
+        assert!(Partition::new("/dev/sda", 2048).is_err());
+        // Disks named with a trailing number are not partitions
+        assert!(Partition::new("/dev/nvme0n1", 2048).is_err());
+        assert!(Partition::new("/dev/mmcblk0", 2048).is_err());
+        assert_eq!(
+            Partition::new("/dev/disk/by-id/ata-XYZ-part2", 0)
+                .unwrap()
+                .designation(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_partition_type() {
+        let cases = [
+            (
+                "C12A7328-F81F-11D2-BA4B-00A0C93EC93B",
+                PartitionType::Gpt(String::from("C12A7328-F81F-11D2-BA4B-00A0C93EC93B")),
+            ),
+            ("83", PartitionType::Mbr(0x83)),
+            ("5", PartitionType::Mbr(0x05)),
+            ("ef", PartitionType::Mbr(0xef)),
+            ("L", PartitionType::Alias(String::from("L"))),
+            ("linux", PartitionType::Alias(String::from("linux"))),
+        ];
+
+        for (text, expected) in cases {
+            let parsed = PartitionType::parse(text);
+            assert_eq!(parsed, expected);
+            assert_eq!(parsed.to_string(), text);
+        }
     }
 
     // Test if Vec<Partition> can actually be sorted by start_block
@@ -133,10 +527,10 @@ pub mod partition_tests {
         let (mut sda, _linux_blk) =
             crate::disk::Disk::new_disk_without_parts("/dev/sda").unwrap();
 
-        let expecteds = vec![p1969.clone(), p2022.clone(), p2048.clone(), p2069.clone()];
+        let expecteds = [p1969.clone(), p2022.clone(), p2048.clone(), p2069.clone()];
         sda.partitions = vec![p2048, p2069, p2022, p1969];
 
-        sda.partitions.sort_by(|a, b| a.start_block.cmp(&b.start_block));
+        sda.partitions.sort_by_key(|part| part.start_block);
         for (i, sorted) in sda.partitions.iter().enumerate() {
             let expected = expecteds.get(i).unwrap();
             assert_eq!(sorted, expected);
@@ -149,15 +543,18 @@ pub mod partition_tests {
             name: String::from("/dev/mmcblk11p2"),
             designation: 2,
             start_block: 2048,
-            extras: vec![String::from("")],
+            ..Default::default()
         };
 
-        match m1.redesignate(block::LinuxBlockDevice::MMCBLK, 1) {
-            Err(err) => {
-                panic!("error redesignating partition: {}", err)
-            }
-            _ => {}
-        }
+        let renaming = match m1.redesignate("/dev/mmcblk11", 1) {
+            Ok(renaming) => renaming,
+            Err(err) => panic!("error redesignating partition: {}", err),
+        };
+
+        assert_eq!(renaming.old_name, "/dev/mmcblk11p2");
+        assert_eq!(renaming.old_designation, 2);
+        assert_eq!(renaming.new_name, "/dev/mmcblk11p1");
+        assert_eq!(renaming.new_designation, 1);
 
         assert_eq!(m1.name, "/dev/mmcblk11p1");
         assert_eq!(m1.designation, 1);
@@ -166,14 +563,11 @@ pub mod partition_tests {
             name: String::from("/dev/nvme0n75p2"),
             designation: 2,
             start_block: 2048,
-            extras: vec![String::from("")],
+            ..Default::default()
         };
 
-        match n1.redesignate(block::LinuxBlockDevice::NVME, 1) {
-            Err(err) => {
-                panic!("error redesignating partition: {}", err)
-            }
-            _ => {}
+        if let Err(err) = n1.redesignate("/dev/nvme0n75", 1) {
+            panic!("error redesignating partition: {}", err)
         }
 
         assert_eq!(n1.name, "/dev/nvme0n75p1");
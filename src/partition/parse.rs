@@ -1,90 +1,170 @@
-use super::{Partition};
-use crate::error::RegexCapturesError;
+use super::{designation_from_name, token, Partition, PartitionType, RawField};
+use crate::error::{ParseError, Span};
 
 use lazy_static::lazy_static;
 use regex::Regex;
-use anyhow::{Error, Result, Context};
 
-const SFDISK_PARTITION_LINE_PATTERN: &str = r"(?P<full_path>/dev/(\w+(?P<part_num>\d+)))\s+:\s+(:?start=\s+)(?P<start_block>\d+)[,](?P<rest>.*)";
+const SFDISK_PARTITION_LINE_PATTERN: &str = r"^\s*(?P<full_path>\S*?(?P<part_num>\d+))\s+:\s+(?P<start_field>start=\s*(?P<start_block>\d+))\s*(?:,(?P<rest>.*))?$";
 
 lazy_static! {
     static ref PARTITION_LINE_REGEX: Regex =
         Regex::new(SFDISK_PARTITION_LINE_PATTERN).unwrap();
 }
 
+/// Reports whether `line` looks like a partition line of `sfdisk -d` output, e.g.
+/// `/dev/sda1 : start= 2048, size= 409600`.
 pub fn is_sfdisk_partition_line(line: &'_ str) -> bool {
     PARTITION_LINE_REGEX.is_match(line)
 }
 
-pub fn parse_sfdisk_partition_line(line: &'_ str) -> Result<Partition> {
-    let caps = PARTITION_LINE_REGEX.captures(line);
-    if caps.is_none() {
-        return Err(Error::from(RegexCapturesError)).with_context(|| {
-            String::from("none match was captured")
-        });
+/// Parses one partition line, and returns it with where its name is in `line`.
+/// Spans point into `line`, with line number 0.
+pub fn parse_sfdisk_partition_line(
+    line: &'_ str,
+) -> Result<(Partition, Span), ParseError> {
+    let caps = match PARTITION_LINE_REGEX.captures(line) {
+        Some(caps) => caps,
+        None => {
+            return Err(ParseError::UnrecognizedLine(Span::new(
+                0,
+                0..line.len(),
+                line,
+            )));
+        }
+    };
+
+    let mut part = Partition::default();
+
+    // All but `rest` always take part in a match
+    let full_path = caps.name("full_path").unwrap();
+    part.name = String::from(full_path.as_str());
+
+    // Take the number the way the JSON parser does, not from `part_num`
+    part.designation = designation_from_name(full_path.as_str())
+        .map_err(|err| err.within(full_path.start(), line))?;
+
+    let start_block = caps.name("start_block").unwrap();
+    part.start_block = match str::parse::<u64>(start_block.as_str()) {
+        Ok(num) => num,
+        Err(_) => {
+            return Err(ParseError::InvalidNumber(Span::new(
+                0,
+                start_block.range(),
+                line,
+            )));
+        }
+    };
+
+    part.fields.push(RawField {
+        lead: String::new(),
+        text: String::from(caps.name("start_field").unwrap().as_str()),
+    });
+
+    // A line with only `start=` has no rest
+    if let Some(rest) = caps.name("rest") {
+        let offset = rest.start();
+        let rest = rest.as_str();
+        let fields = token::tokenize(rest).map_err(|err| err.within(offset, line))?;
+        let mut field_end = start_block.end();
+        for field in fields {
+            let span =
+                Span::new(0, field.span.start + offset..field.span.end + offset, line);
+            let raw = &rest[field.span.clone()];
+            part.fields.push(RawField {
+                lead: String::from(&line[field_end..span.columns.start]),
+                text: String::from(raw),
+            });
+            field_end = span.columns.end;
+            parse_sfdisk_partition_field(&mut part, field, raw, span)?;
+        }
     }
 
+    Ok((part, Span::new(0, full_path.range(), line)))
+}
+
+/// Parses the text of a single field, e.g. `type=07`, into a partition with only that
+/// field set, for comparing it with the field of a partition parsed from a line.
+pub(crate) fn parse_field_text(text: &str) -> Option<Partition> {
     let mut part = Partition::default();
-    let mut extras: Vec<String> = Vec::new();
-    let caps = caps.unwrap();
-
-    if let Some(full_path) = caps.name("full_path") {
-        part.name = String::from(full_path.as_str());
-    } else {
-        return Err(Error::from(RegexCapturesError))
-            .with_context(|| format!("key full_path not matched in {}", line));
+    let mut fields = token::tokenize(text).ok()?.into_iter();
+    let field = fields.next()?;
+    if fields.next().is_some() {
+        return None;
     }
 
-    if let Some(part_num) = caps.name("part_num") {
-        let part_num = part_num.as_str();
-        match str::parse::<usize>(part_num) {
-            Ok(num) => {
-                part.designation = num;
-            }
-            Err(err) => {
-                return Err(Error::from(err)).with_context(|| {
-                    format!("error parsing decimal string to usize: {}", part_num)
-                });
+    if field.key == "start" {
+        part.start_block = str::parse::<u64>(field.value.as_deref()?).ok()?;
+        return Some(part);
+    }
+    parse_sfdisk_partition_field(&mut part, field, text, Span::default()).ok()?;
+
+    Some(part)
+}
+
+/// Parses a single tokenized field of a partition line into its typed field on `part`.
+/// The old sfdisk key `Id=` is read like `type=`.
+/// Fields with unknown keys are kept verbatim (`raw`) in `part.extras`.
+fn parse_sfdisk_partition_field(
+    part: &mut Partition,
+    field: token::Field,
+    raw: &str,
+    span: Span,
+) -> Result<(), ParseError> {
+    let value = match field.value {
+        Some(value) => value,
+        None => {
+            if field.key == "bootable" {
+                part.bootable = true;
+            } else {
+                part.extras.push(String::from(raw));
             }
+            return Ok(());
         }
-    } else {
-        return Err(Error::new(RegexCapturesError))
-            .with_context(|| String::from("missing partition number"));
-    }
+    };
 
-    if let Some(start_block) = caps.name("start_block") {
-        let start_block = start_block.as_str();
-        match str::parse::<usize>(start_block) {
+    match field.key.as_str() {
+        "size" => match str::parse::<u64>(&value) {
             Ok(num) => {
-                part.start_block = num;
+                part.size = Some(num);
             }
-            Err(err) => {
-                return Err(Error::from(err))
-                    .with_context(|| String::from("failed to parse start_block"))
+            Err(_) => {
+                return Err(ParseError::InvalidNumber(value_span(raw, span)));
             }
+        },
+        "type" | "Id" => {
+            part.part_type = Some(PartitionType::parse(&value));
         }
-    } else {
-        return Err(Error::from(RegexCapturesError))
-            .with_context(|| String::from("missing full partition name"));
-    }
-
-    if let Some(rest) = caps.name("rest") {
-        let splited: std::str::SplitWhitespace = rest.as_str().split_whitespace();
-        for extra in splited.into_iter().collect::<Vec<&str>>().iter() {
-            extras.push(String::from(*extra));
+        "uuid" => {
+            part.uuid = Some(value);
+        }
+        "name" => {
+            part.label = Some(value);
+        }
+        "attrs" => {
+            part.attrs = value.split_whitespace().map(String::from).collect();
+        }
+        _ => {
+            part.extras.push(String::from(raw));
         }
-    } else {
-        return Err(Error::from(RegexCapturesError))
-            .with_context(|| String::from("missing the rest of the line"));
     }
 
-    part.extras = extras;
-    Ok(part)
+    Ok(())
+}
+
+/// Narrows the `span` of a whole `key=value` field (`raw`) down to its value.
+fn value_span(raw: &str, span: Span) -> Span {
+    let after_eq = raw.find('=').map_or(0, |eq| eq + 1);
+    let blanks = raw[after_eq..].len() - raw[after_eq..].trim_start().len();
+
+    Span {
+        columns: span.columns.start + after_eq + blanks..span.columns.end,
+        ..span
+    }
 }
 
 #[cfg(test)]
 mod test_parse {
-    use super::Partition;
+    use super::{Partition, PartitionType};
     use super::{parse_sfdisk_partition_line, SFDISK_PARTITION_LINE_PATTERN};
     use crate::linux::block;
     use crate::partition::parse::is_sfdisk_partition_line;
@@ -108,6 +188,12 @@ mod test_parse {
         for key in KEYS.iter() {
             assert!(caps.name(key).is_some());
         }
+
+        // Only whole lines match, not text after a comment marker
+        assert!(is_sfdisk_partition_line("  /dev/sda1 : start= 2048"));
+        assert!(!is_sfdisk_partition_line(
+            "# /dev/sda9 : start=2048, size=1024"
+        ));
     }
 
     #[test]
@@ -123,16 +209,13 @@ mod test_parse {
                         designation: 1,
                         start_block: 2048,
                         name: String::from("/dev/sda1"),
-                        extras: vec![
-                            String::from("size="),
-                            String::from("60086239,"),
-                            String::from("type=0FC63DAF-8483-4772-8E79-3D69D8477DE4,"),
-                            String::from("uuid=AAAAAAAA-BBBB-CCCC-DDDD-EEEEEEEEEEEE,"),
-                            String::from("it"),
-                            String::from("ain't"),
-                            String::from("me"),
-                            String::from("babe"),
-                        ],
+                        size: Some(60086239),
+                        part_type: Some(PartitionType::Gpt(String::from(
+                            "0FC63DAF-8483-4772-8E79-3D69D8477DE4",
+                        ))),
+                        uuid: Some(String::from("AAAAAAAA-BBBB-CCCC-DDDD-EEEEEEEEEEEE")),
+                        extras: vec![String::from("it ain't me    babe")],
+                        ..Default::default()
                 },
             ),
             (
@@ -145,16 +228,13 @@ mod test_parse {
                         designation: 1,
                         start_block: 2048,
                         name: String::from("/dev/nvme0n1p1"),
-                        extras: vec![
-                            String::from("size="),
-                            String::from("60086239,"),
-                            String::from("type=0FC63DAF-8483-4772-8E79-3D69D8477DE4,"),
-                            String::from("uuid=AAAAAAAA-BBBB-CCCC-DDDD-EEEEEEEEEEEE,"),
-                            String::from("it"),
-                            String::from("ain't"),
-                            String::from("me"),
-                            String::from("babe"),
-                        ],
+                        size: Some(60086239),
+                        part_type: Some(PartitionType::Gpt(String::from(
+                            "0FC63DAF-8483-4772-8E79-3D69D8477DE4",
+                        ))),
+                        uuid: Some(String::from("AAAAAAAA-BBBB-CCCC-DDDD-EEEEEEEEEEEE")),
+                        extras: vec![String::from("it ain't me    babe")],
+                        ..Default::default()
                 },
             ),
         ]);
@@ -168,19 +248,82 @@ mod test_parse {
             assert!(is_sfdisk_partition_line(part_line));
             // let this_linux_name = test_tuple.2;
             let parsed = match parse_sfdisk_partition_line(part_line) {
-                Ok(part) => part,
+                Ok((part, _)) => part,
                 Err(err) => {
                     eprintln!("error parsing: {}", err);
                     Partition::default()
                 }
             };
 
-            if parsed != *expected_part {
-                assert_eq!(expected_part.designation, parsed.designation);
-                assert_eq!(expected_part.start_block, parsed.start_block);
-                assert_eq!(expected_part.name, parsed.name);
-                assert_eq!(expected_part.extras, parsed.extras);
-            }
+            assert_eq!(parsed, *expected_part);
+        }
+    }
+
+    #[test]
+    fn test_parse_fields() {
+        let line = "/dev/sda10 : start=2048, size=409600, type=C12A7328-F81F-11D2-BA4B-00A0C93EC93B, uuid=AAAAAAAA-BBBB-CCCC-DDDD-EEEEEEEEEEEE, name=\"EFI\", attrs=\"RequiredPartition GUID:63\", foo=bar";
+        let part = parse_sfdisk_partition_line(line).unwrap().0;
+
+        assert_eq!(part.designation, 10);
+        assert_eq!(part.start_block, 2048);
+        assert_eq!(part.size, Some(409600));
+        assert_eq!(part.label.as_deref(), Some("EFI"));
+        assert_eq!(part.attrs, vec!["RequiredPartition", "GUID:63"]);
+        assert_eq!(part.extras, vec!["foo=bar"]);
+
+        let dos_line =
+            "/dev/sda1 : start=        2048, size=     1048576, type=83, bootable";
+        let part = parse_sfdisk_partition_line(dos_line).unwrap().0;
+        assert_eq!(part.part_type, Some(PartitionType::Mbr(0x83)));
+        assert!(part.bootable);
+        assert_eq!(part.to_string(), dos_line);
+
+        let bad_size = "/dev/sda1 : start= 2048, size= lots";
+        let err = parse_sfdisk_partition_line(bad_size).unwrap_err();
+        assert_eq!(err.span().snippet(), "lots");
+        assert_eq!(err.span().columns, 31..35);
+    }
+
+    // Lines as printed by `sfdisk -d` must come back out byte-for-byte.
+    #[test]
+    fn test_parse_faithful() {
+        let lines = [
+            r#"/dev/sda1 : start=        2048, size=     1048576, type=C12A7328-F81F-11D2-BA4B-00A0C93EC93B, uuid=AAAAAAAA-BBBB-CCCC-DDDD-EEEEEEEEEEEE, name="EFI system  partition""#,
+            r#"/dev/sda2 : start=     1050624, size=    67108864, type=0FC63DAF-8483-4772-8E79-3D69D8477DE4, uuid=FFFFFFFF-GGGG-HHHH-IIII-JJJJJJJJJJJJ, name="root, \x22main\x22 \x24HOME", attrs="RequiredPartition GUID:48,49""#,
+            r#"/dev/sda3 : start=    68159488, size=      409600, type=83, bootable, foo="bar, baz""#,
+            r#"/dev/disk/by-path/pci-0000:00:1f.2-ata-1-part12 : start=        2048, size=     1048576, type=83"#,
+        ];
+
+        for line in lines {
+            let part = parse_sfdisk_partition_line(line).unwrap().0;
+            assert_eq!(part.to_string(), line);
         }
+
+        let part = parse_sfdisk_partition_line(lines[1]).unwrap().0;
+        assert_eq!(part.label.as_deref(), Some("root, \"main\" $HOME"));
+        assert_eq!(part.attrs, vec!["RequiredPartition", "GUID:48,49"]);
+    }
+
+    // Hand-written lines keep their order and spelling, and only changed fields are
+    // reformatted in place.
+    #[test]
+    fn test_parse_round_trip() {
+        let line = r#"/dev/sda1 : start=2048, type=07, size=100, foo=bar, name="a  b""#;
+        let mut part = parse_sfdisk_partition_line(line).unwrap().0;
+        assert_eq!(part.part_type, Some(PartitionType::Mbr(0x07)));
+        assert_eq!(part.to_string(), line);
+
+        part.size = Some(200);
+        part.label = None;
+        part.name = String::from("/dev/sda2");
+        assert_eq!(
+            part.to_string(),
+            "/dev/sda2 : start=2048, type=07, size=         200, foo=bar"
+        );
+
+        let old_style = "/dev/sda1 : start=63,size=1000,Id=0C";
+        let part = parse_sfdisk_partition_line(old_style).unwrap().0;
+        assert_eq!(part.part_type, Some(PartitionType::Mbr(0x0c)));
+        assert_eq!(part.to_string(), old_style);
     }
 }
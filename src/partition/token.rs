@@ -0,0 +1,242 @@
+use crate::error::{ParseError, Span};
+
+use std::ops::Range;
+
+/// A single field of an sfdisk partition line, e.g. `size= 409600` or `name="EFI, boot"`.
+/// Bare fields without `=` (e.g. `bootable`) have the whole field text as `key`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Field {
+    pub key: String,
+    /// Unquoted and unescaped value, if the field has one
+    pub value: Option<String>,
+    /// Byte range of the whole field (excluding surrounding blanks and separators) in the tokenized text
+    pub span: Range<usize>,
+}
+
+/// Splits the `key=value, key="quoted, value"` field list of an sfdisk partition line into fields.
+/// Quoted values may contain commas and blanks, and use the sfdisk escapes `\xHH`, `\"` and `\\`.
+/// Error spans are relative to `s`.
+pub fn tokenize(s: &str) -> Result<Vec<Field>, ParseError> {
+    let bytes = s.as_bytes();
+    let mut fields = Vec::new();
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        pos = skip_blanks(bytes, pos);
+        let field_start = pos;
+
+        // Key, or the whole text of a bare field
+        while pos < bytes.len() && bytes[pos] != b'=' && bytes[pos] != b',' {
+            if bytes[pos] == b'"' {
+                pos = skip_quoted(s, pos)?;
+                continue;
+            }
+            pos += 1;
+        }
+        let key = s[field_start..pos].trim_end();
+
+        if pos >= bytes.len() || bytes[pos] == b',' {
+            if !key.is_empty() {
+                fields.push(Field {
+                    key: String::from(key),
+                    value: None,
+                    span: field_start..field_start + key.len(),
+                });
+            }
+            pos += 1;
+            continue;
+        }
+
+        // Skip '=' and the blanks sfdisk uses to pad numbers
+        pos = skip_blanks(bytes, pos + 1);
+        let value_start = pos;
+        let value: String;
+
+        if pos < bytes.len() && bytes[pos] == b'"' {
+            let value_end = skip_quoted(s, pos)?;
+            value = unescape(&s[value_start + 1..value_end - 1])
+                .map_err(|err| err.within(value_start + 1, s))?;
+            pos = value_end;
+        } else {
+            while pos < bytes.len() && bytes[pos] != b',' {
+                pos += 1;
+            }
+            value = String::from(s[value_start..pos].trim_end());
+        }
+
+        let field_end = s[..pos].trim_end().len();
+        pos = skip_blanks(bytes, pos);
+        if pos < bytes.len() && bytes[pos] != b',' {
+            let garbage_end = s[pos..].find(',').map_or(s.len(), |comma| pos + comma);
+            let garbage_end = s[..garbage_end].trim_end().len();
+            return Err(ParseError::TrailingText(Span::new(0, pos..garbage_end, s)));
+        }
+
+        fields.push(Field {
+            key: String::from(key),
+            value: Some(value),
+            span: field_start..field_end,
+        });
+        pos += 1;
+    }
+
+    Ok(fields)
+}
+
+/// Quotes a value the way sfdisk does, escaping `"`, `\`, `` ` ``, `$`
+/// and non-printable bytes as `\xHH`.
+pub fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for byte in value.bytes() {
+        match byte {
+            b'"' | b'\\' | b'`' | b'$' => quoted.push_str(&format!("\\x{:02x}", byte)),
+            0x20..=0x7e => quoted.push(byte as char),
+            _ => quoted.push_str(&format!("\\x{:02x}", byte)),
+        }
+    }
+    quoted.push('"');
+
+    quoted
+}
+
+fn skip_blanks(bytes: &[u8], mut pos: usize) -> usize {
+    while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+        pos += 1;
+    }
+
+    pos
+}
+
+/// Returns the position just after the closing quote of the quoted string starting at `start`.
+fn skip_quoted(s: &str, start: usize) -> Result<usize, ParseError> {
+    let bytes = s.as_bytes();
+    let mut pos = start + 1;
+
+    while pos < bytes.len() {
+        match bytes[pos] {
+            b'\\' => pos += 2,
+            b'"' => return Ok(pos + 1),
+            _ => pos += 1,
+        }
+    }
+
+    Err(ParseError::UnterminatedQuote(Span::new(
+        0,
+        start..s.trim_end().len(),
+        s,
+    )))
+}
+
+/// Resolves the escapes of quoted value `s`. Error spans are relative to `s`.
+fn unescape(s: &str) -> Result<String, ParseError> {
+    let bytes = s.as_bytes();
+    let mut unescaped: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        if bytes[pos] != b'\\' {
+            unescaped.push(bytes[pos]);
+            pos += 1;
+            continue;
+        }
+
+        match bytes.get(pos + 1) {
+            Some(b'x') => {
+                let hex = s.get(pos + 2..pos + 4).unwrap_or_default();
+                match u8::from_str_radix(hex, 16) {
+                    Ok(byte) if hex.len() == 2 => unescaped.push(byte),
+                    _ => {
+                        let end = (pos + 4).min(s.len());
+                        return Err(ParseError::InvalidEscape(Span::new(0, pos..end, s)));
+                    }
+                }
+                pos += 4;
+            }
+            Some(escaped) => {
+                unescaped.push(*escaped);
+                pos += 2;
+            }
+            None => {
+                return Err(ParseError::InvalidEscape(Span::new(0, pos..pos + 1, s)));
+            }
+        }
+    }
+
+    match String::from_utf8(unescaped) {
+        Ok(text) => Ok(text),
+        Err(_) => Err(ParseError::InvalidUtf8(Span::new(0, 0..s.len(), s))),
+    }
+}
+
+#[cfg(test)]
+mod token_test {
+    use super::{quote, tokenize, Field};
+
+    #[test]
+    fn test_tokenize() {
+        let s = r#" size=      409600, type=83, name="EFI system  partition, \x22v2\x22", bootable, attrs="GUID:48,49""#;
+        let fields = tokenize(s).unwrap();
+
+        let expecteds = [
+            ("size", Some("409600")),
+            ("type", Some("83")),
+            ("name", Some("EFI system  partition, \"v2\"")),
+            ("bootable", None),
+            ("attrs", Some("GUID:48,49")),
+        ];
+        assert_eq!(fields.len(), expecteds.len());
+        for (field, (key, value)) in fields.iter().zip(expecteds.iter()) {
+            assert_eq!(field.key, *key);
+            assert_eq!(field.value.as_deref(), *value);
+        }
+
+        assert_eq!(
+            &s[fields[2].span.clone()],
+            r#"name="EFI system  partition, \x22v2\x22""#
+        );
+        assert_eq!(&s[fields[3].span.clone()], "bootable");
+    }
+
+    #[test]
+    fn test_tokenize_escapes() {
+        let fields = tokenize(r#"name="a \"quoted\" \\ name""#).unwrap();
+        assert_eq!(
+            fields,
+            vec![Field {
+                key: String::from("name"),
+                value: Some(String::from(r#"a "quoted" \ name"#)),
+                span: 0..27,
+            }]
+        );
+
+        let errs = [
+            (r#"name="unterminated"#, r#""unterminated"#),
+            (r#"name="bad \xZZ escape""#, r#"\xZZ"#),
+            (r#"name="trailing" garbage, type=83"#, "garbage"),
+        ];
+        for (s, snippet) in errs {
+            let err = tokenize(s).unwrap_err();
+            assert_eq!(err.span().snippet(), snippet, "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_quote() {
+        let names = [
+            "EFI system  partition",
+            "a, b",
+            "say \"hi\" for $5",
+            "Données",
+        ];
+        for name in names {
+            let quoted = quote(name);
+            let field = format!("name={}", quoted);
+            let parsed = tokenize(&field).unwrap();
+            assert_eq!(parsed[0].value.as_deref(), Some(name));
+        }
+
+        assert_eq!(quote("say \"hi\""), r#""say \x22hi\x22""#);
+        assert_eq!(quote("é"), r#""\xc3\xa9""#);
+    }
+}
@@ -0,0 +1,503 @@
+//! Tables and summaries of disks, renamings and GPT changes for the command line.
+//!
+//! Each function formats one kind of output of the command line as a string, so that
+//! other front ends can print the same:
+//!
+//! - `disk_info`, `free_space` and `alignment` describe a parsed disk, like the `info`,
+//!   `free` and `align` commands.
+//! - `mapping_table` and `mapping_json` list the renamings of `Disk::rearrange`.
+//! - `gpt_changes` lists the bytes `write_gpt` changed in a disk image.
+//! - `designation_note` annotates the lines of a `diff::unified` diff.
+//!
+//! `table` and `human_size` are the building blocks of the others.
+
+use crate::disk::align::Alignment;
+use crate::disk::Disk;
+use crate::gpt::write::ByteChange;
+use crate::partition::Renaming;
+
+use anyhow::{Error, Result, Context};
+use serde_json::{json, Value};
+
+/// Formats rows as left-aligned columns separated by 2 spaces. The first row is the heading.
+pub fn table(rows: &[Vec<String>]) -> String {
+    let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let widths: Vec<usize> = (0..columns)
+        .map(|col| {
+            rows.iter()
+                .filter_map(|row| row.get(col))
+                .map(|cell| cell.chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    rows.iter()
+        .map(|row| {
+            let cells: Vec<String> = row
+                .iter()
+                .enumerate()
+                .map(|(col, cell)| format!("{:<width$}", cell, width = widths[col]))
+                .collect();
+            String::from(cells.join("  ").trim_end())
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Formats the old-to-new partition mapping returned by `Disk::rearrange` as a text table.
+pub fn mapping_table(renamings: &[Renaming]) -> String {
+    let mut rows: Vec<Vec<String>> = vec![vec![
+        String::from("OLD"),
+        String::from("NEW"),
+        String::from("UUID"),
+    ]];
+    for renaming in renamings {
+        rows.push(vec![
+            renaming.old_name.clone(),
+            renaming.new_name.clone(),
+            renaming.uuid.clone().unwrap_or_else(|| String::from("-")),
+        ]);
+    }
+
+    table(&rows)
+}
+
+/// Returns `# OLD -> NEW` designations for a dumped partition line, if it belongs to
+/// one of `renamings`. `removed` lines are looked up by the old partition name,
+/// added lines by the new one. Both `sfdisk -d` lines and `"node"` JSON lines are recognized.
+pub fn designation_note(
+    renamings: &[Renaming],
+    line: &str,
+    removed: bool,
+) -> Option<String> {
+    let trimmed = line.trim_start();
+    let name = match trimmed.strip_prefix("\"node\":") {
+        Some(value) => value.trim().trim_end_matches(',').trim_matches('"'),
+        None => trimmed.split_whitespace().next()?,
+    };
+
+    let renaming = renamings.iter().find(|renaming| {
+        if removed {
+            renaming.old_name == name
+        } else {
+            renaming.new_name == name
+        }
+    })?;
+
+    Some(format!(
+        "# {} -> {}",
+        renaming.old_designation, renaming.new_designation
+    ))
+}
+
+/// Formats a summary of the disk header followed by a table of its partitions.
+pub fn disk_info(disk: &Disk) -> String {
+    let header = &disk.header;
+    let or_dash = |value: Option<String>| value.unwrap_or_else(|| String::from("-"));
+
+    let summary = vec![
+        vec![String::from("device:"), header.device.clone()],
+        vec![
+            String::from("label:"),
+            or_dash(header.label.map(|l| l.to_string())),
+        ],
+        vec![String::from("label-id:"), or_dash(header.label_id.clone())],
+        vec![
+            String::from("sector-size:"),
+            or_dash(header.sector_size.map(|n| n.to_string())),
+        ],
+        vec![
+            String::from("first-lba:"),
+            or_dash(header.first_lba.map(|n| n.to_string())),
+        ],
+        vec![
+            String::from("last-lba:"),
+            or_dash(header.last_lba.map(|n| n.to_string())),
+        ],
+        vec![
+            String::from("partitions:"),
+            disk.partitions.len().to_string(),
+        ],
+    ];
+
+    let mut rows: Vec<Vec<String>> = vec![vec![
+        String::from("DEVICE"),
+        String::from("START"),
+        String::from("END"),
+        String::from("SECTORS"),
+        String::from("TYPE"),
+        String::from("NAME"),
+    ]];
+    for part in &disk.partitions {
+        rows.push(vec![
+            part.name.clone(),
+            part.start_block.to_string(),
+            or_dash(part.end_block().map(|n| n.to_string())),
+            or_dash(part.size.map(|n| n.to_string())),
+            or_dash(part.part_type.as_ref().map(|t| t.to_string())),
+            or_dash(part.label.clone()),
+        ]);
+    }
+
+    format!("{}\n\n{}", table(&summary), table(&rows))
+}
+
+/// Formats a size in bytes with binary units the way `sfdisk` does, e.g. `512B`, `1M` or `2.5G`.
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["B", "K", "M", "G", "T", "P"];
+
+    let mut unit = 0;
+    let mut size = bytes as f64;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    let rounded = format!("{:.1}", size);
+    let rounded = rounded.strip_suffix(".0").unwrap_or(&rounded);
+    format!("{}{}", rounded, UNITS[unit])
+}
+
+/// Formats the unallocated gaps of the disk like `sfdisk -F`, with whether each gap
+/// starts on the `grain:` boundary.
+pub fn free_space(disk: &Disk) -> String {
+    let sector_size = disk.sector_size();
+    let grain = disk.grain_sectors();
+    let gaps = disk.free_space();
+    let total = gaps
+        .iter()
+        .fold(0u64, |total, gap| total.saturating_add(gap.sectors()));
+    // Saturate instead of overflowing on disks with a bogus `last-lba:`
+    let total_bytes = total.saturating_mul(sector_size);
+
+    let mut rows: Vec<Vec<String>> = vec![vec![
+        String::from("START"),
+        String::from("END"),
+        String::from("SECTORS"),
+        String::from("SIZE"),
+        String::from("ALIGNED"),
+    ]];
+    for gap in &gaps {
+        let aligned = if gap.start % grain == 0 { "yes" } else { "no" };
+        rows.push(vec![
+            gap.start.to_string(),
+            gap.end.to_string(),
+            gap.sectors().to_string(),
+            human_size(gap.sectors().saturating_mul(sector_size)),
+            String::from(aligned),
+        ]);
+    }
+
+    let summary = format!(
+        "Unpartitioned space {}: {}, {} bytes, {} sectors
+Units: sectors of 1 * {} = {} bytes",
+        disk.name,
+        human_size(total_bytes),
+        total_bytes,
+        total,
+        sector_size,
+        sector_size
+    );
+    if gaps.is_empty() {
+        return summary;
+    }
+
+    format!(
+        "{}
+
+{}",
+        summary,
+        table(&rows)
+    )
+}
+
+/// Formats the partitions that are not aligned to `alignment` as a table, with how many
+/// sectors their start and size are off, and the aligned start to move them to.
+pub fn alignment(disk: &Disk, alignment: &Alignment) -> Result<String> {
+    let boundary = alignment.sectors(disk)?;
+    let summary = format!(
+        "Alignment {}: {} sectors ({})",
+        disk.name,
+        boundary,
+        human_size(boundary * disk.sector_size())
+    );
+
+    let misalignments = disk.misalignments(alignment)?;
+    if misalignments.is_empty() {
+        return Ok(format!("{}\nAll partitions are aligned", summary));
+    }
+
+    let mut rows: Vec<Vec<String>> = vec![vec![
+        String::from("DEVICE"),
+        String::from("START"),
+        String::from("START-OFFSET"),
+        String::from("SIZE-REMAINDER"),
+        String::from("SUGGESTED-START"),
+    ]];
+    for misalignment in &misalignments {
+        rows.push(vec![
+            misalignment.partition.clone(),
+            misalignment.start.to_string(),
+            misalignment.start_offset.to_string(),
+            misalignment.size_remainder.to_string(),
+            misalignment
+                .suggested_start
+                .map_or_else(|| String::from("-"), |n| n.to_string()),
+        ]);
+    }
+
+    Ok(format!("{}\n\n{}", summary, table(&rows)))
+}
+
+/// Formats the byte ranges `gpt::write::write_gpt` changed in the image `path`, or
+/// would change if `dry_run`, with their offsets in bytes.
+pub fn gpt_changes(path: &str, changes: &[ByteChange], dry_run: bool) -> String {
+    if changes.is_empty() {
+        return format!(
+            "Nothing to write to {}, its GPT is already in this order",
+            path
+        );
+    }
+
+    let bytes: usize = changes.iter().map(|change| change.new.len()).sum();
+    let summary = format!(
+        "{} {} bytes in {} ranges of {}",
+        if dry_run { "Would write" } else { "Wrote" },
+        bytes,
+        changes.len(),
+        path
+    );
+
+    let mut rows: Vec<Vec<String>> = vec![vec![
+        String::from("OFFSET"),
+        String::from("BYTES"),
+        String::from("REGION"),
+    ]];
+    for change in changes {
+        rows.push(vec![
+            change.offset.to_string(),
+            change.new.len().to_string(),
+            change.region.to_string(),
+        ]);
+    }
+
+    format!("{}\n\n{}", summary, table(&rows))
+}
+
+/// Formats the old-to-new partition mapping returned by `Disk::rearrange` as a JSON array.
+pub fn mapping_json(renamings: &[Renaming]) -> Result<String> {
+    let entries: Vec<Value> = renamings
+        .iter()
+        .map(|renaming| {
+            json!({
+                "old_name": renaming.old_name,
+                "old_designation": renaming.old_designation,
+                "new_name": renaming.new_name,
+                "new_designation": renaming.new_designation,
+                "uuid": renaming.uuid,
+            })
+        })
+        .collect();
+
+    match serde_json::to_string_pretty(&entries) {
+        Ok(text) => Ok(text),
+        Err(err) => Err(Error::from(err))
+            .with_context(|| String::from("failed to serialize mapping")),
+    }
+}
+
+#[cfg(test)]
+mod report_test {
+    use super::{
+        alignment, designation_note, disk_info, free_space, gpt_changes, human_size,
+        mapping_json, mapping_table,
+    };
+    use crate::gpt::write::{ByteChange, GptRegion};
+    use crate::disk::align::Alignment;
+    use crate::disk::parse_sfdisk_full_disk;
+    use crate::partition::Renaming;
+
+    fn renamings() -> Vec<Renaming> {
+        vec![
+            Renaming {
+                old_name: String::from("/dev/sda10"),
+                old_designation: 10,
+                new_name: String::from("/dev/sda1"),
+                new_designation: 1,
+                uuid: Some(String::from("AAAAAAAA-BBBB-CCCC-DDDD-EEEEEEEEEEEE")),
+            },
+            Renaming {
+                old_name: String::from("/dev/sda1"),
+                old_designation: 1,
+                new_name: String::from("/dev/sda2"),
+                new_designation: 2,
+                uuid: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_mapping_table() {
+        assert_eq!(
+            mapping_table(&renamings()),
+            "OLD         NEW        UUID
+/dev/sda10  /dev/sda1  AAAAAAAA-BBBB-CCCC-DDDD-EEEEEEEEEEEE
+/dev/sda1   /dev/sda2  -"
+        );
+    }
+
+    #[test]
+    fn test_mapping_json() {
+        let parsed: serde_json::Value =
+            serde_json::from_str(&mapping_json(&renamings()).unwrap()).unwrap();
+
+        assert_eq!(parsed[0]["old_name"], "/dev/sda10");
+        assert_eq!(parsed[0]["new_designation"], 1);
+        assert_eq!(parsed[1]["uuid"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn test_designation_note() {
+        let renamings = renamings();
+
+        assert_eq!(
+            designation_note(&renamings, "/dev/sda10 : start=        2048", true),
+            Some(String::from("# 10 -> 1"))
+        );
+        assert_eq!(
+            designation_note(&renamings, "/dev/sda1 : start=        2048", false),
+            Some(String::from("# 10 -> 1"))
+        );
+        assert_eq!(
+            designation_note(&renamings, "        \"node\": \"/dev/sda1\",", true),
+            Some(String::from("# 1 -> 2"))
+        );
+        assert_eq!(designation_note(&renamings, "label: gpt", true), None);
+        assert_eq!(designation_note(&renamings, "", false), None);
+    }
+
+    #[test]
+    fn test_disk_info() {
+        let input = std::fs::read_to_string("./assets/sfdisk_output.txt")
+            .expect("failed to read test text file");
+        let disk = parse_sfdisk_full_disk(input).unwrap();
+
+        assert_eq!(
+            disk_info(&disk),
+            "device:       /dev/sda
+label:        gpt
+label-id:     12345678-2345-6969-3264-A55555555555
+sector-size:  512
+first-lba:    2048
+last-lba:     976773134
+partitions:   4
+
+DEVICE     START      END        SECTORS    TYPE                                  NAME
+/dev/sda1  2048       411647     409600     C12A7328-F81F-11D2-BA4B-00A0C93EC93B  -
+/dev/sda2  411648     67520511   67108864   0FC63DAF-8483-4772-8E79-3D69D8477DE4  -
+/dev/sda3  67520512   101074943  33554432   0FC63DAF-8483-4772-8E79-3D69D8477DE4  -
+/dev/sda4  101074944  976773134  875698191  6A85CF4D-1DD2-11B2-99A6-080020736631  -"
+        );
+    }
+
+    #[test]
+    fn test_human_size() {
+        assert_eq!(human_size(512), "512B");
+        assert_eq!(human_size(1048576), "1M");
+        assert_eq!(human_size(2684354560), "2.5G");
+        assert_eq!(human_size(1023 * 1024), "1023K");
+    }
+
+    #[test]
+    fn test_free_space() {
+        let input = std::fs::read_to_string("./assets/sfdisk_output_dos_ugly.txt")
+            .expect("failed to read ugly DOS test text file");
+        let disk = parse_sfdisk_full_disk(input).unwrap();
+
+        assert_eq!(
+            free_space(&disk),
+            "Unpartitioned space /dev/vda: 3M, 3145216 bytes, 6143 sectors
+Units: sectors of 1 * 512 = 512 bytes
+
+START     END       SECTORS  SIZE     ALIGNED
+1         2047      2047     1023.5K  no
+1050624   1052671   2048     1M       yes
+20971520  20973567  2048     1M       yes"
+        );
+    }
+
+    #[test]
+    fn test_free_space_limits() {
+        let input = "label: gpt
+device: /dev/sda
+first-lba: 34
+last-lba: 18446744073709551615
+";
+        let disk = parse_sfdisk_full_disk(String::from(input)).unwrap();
+
+        assert_eq!(
+            free_space(&disk),
+            "Unpartitioned space /dev/sda: 16384P, 18446744073709551615 bytes, \
+18446744073709551582 sectors
+Units: sectors of 1 * 512 = 512 bytes
+
+START  END                   SECTORS               SIZE    ALIGNED
+34     18446744073709551615  18446744073709551582  16384P  no"
+        );
+    }
+
+    #[test]
+    fn test_alignment() {
+        let input = std::fs::read_to_string("./assets/sfdisk_output.txt")
+            .expect("failed to read test text file");
+        let mut disk = parse_sfdisk_full_disk(input).unwrap();
+
+        assert_eq!(
+            alignment(&disk, &Alignment::default()).unwrap(),
+            "Alignment /dev/sda: 2048 sectors (1M)
+
+DEVICE     START      START-OFFSET  SIZE-REMAINDER  SUGGESTED-START
+/dev/sda4  101074944  0             15              101074944"
+        );
+
+        disk.partitions.pop();
+        assert_eq!(
+            alignment(&disk, &Alignment::Bytes(4096)).unwrap(),
+            "Alignment /dev/sda: 8 sectors (4K)\nAll partitions are aligned"
+        );
+        assert!(alignment(&disk, &Alignment::Bytes(1000)).is_err());
+    }
+
+    #[test]
+    fn test_gpt_changes() {
+        let changes = vec![
+            ByteChange {
+                region: GptRegion::PrimaryHeader,
+                offset: 528,
+                old: vec![0; 4],
+                new: vec![1; 4],
+            },
+            ByteChange {
+                region: GptRegion::PrimaryEntry(0),
+                offset: 1024,
+                old: vec![0; 128],
+                new: vec![1; 128],
+            },
+        ];
+
+        assert_eq!(
+            gpt_changes("disk.img", &changes, true),
+            "Would write 132 bytes in 2 ranges of disk.img
+
+OFFSET  BYTES  REGION
+528     4      primary GPT header
+1024    128    primary GPT entry 1"
+        );
+        assert!(gpt_changes("disk.img", &changes, false).starts_with("Wrote 132 bytes"));
+        assert_eq!(
+            gpt_changes("disk.img", &[], false),
+            "Nothing to write to disk.img, its GPT is already in this order"
+        );
+    }
+}